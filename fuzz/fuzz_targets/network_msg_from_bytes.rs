@@ -0,0 +1,23 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use stableset_net::{comms::NetworkMsg, stableset::StableSetMsg};
+
+fuzz_target!(|data: &[u8]| {
+    let msg = match NetworkMsg::<StableSetMsg>::from_bytes(Bytes::copy_from_slice(data)) {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
+    let _ = msg.verify();
+
+    // whatever decodes must encode back to something decoding the same
+    let bytes = msg.to_bytes().expect("Failed to encode a decoded msg");
+    let decoded =
+        NetworkMsg::<StableSetMsg>::from_bytes(bytes).expect("Failed to decode an encoded msg");
+    assert_eq!(decoded.id, msg.id);
+    assert_eq!(decoded.src, msg.src);
+    assert_eq!(decoded.dst, msg.dst);
+    assert_eq!(decoded.body, msg.body);
+    assert_eq!(decoded.signature, msg.signature);
+});
@@ -6,7 +6,9 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{MsgId, NetworkNode};
+use super::{MsgId, NetworkNode, NodeId, TransportError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use thiserror::Error;
 
 /// The type returned by the `sn_routing` message handling methods.
@@ -25,10 +27,34 @@ pub enum Error {
     AddressNotReachable(#[from] qp2p::RpcError),
     #[error("Content of received msg {0:?} is invalid.")]
     InvalidMsgReceived(MsgId),
+    #[error("Signature of received msg {0:?} does not match its sender.")]
+    InvalidSignature(MsgId),
+    #[error("Received msg {0:?} is meant for another node.")]
+    Misdirected(MsgId),
+    #[error("Msg of {size} bytes is over the limit of {max_size} bytes.")]
+    MessageTooLarge { size: usize, max_size: u64 },
+    #[error("Received bytes do not start with a valid frame header.")]
+    InvalidHeader,
+    #[error("Received msg is encoded in protocol version {0}, which we do not support.")]
+    UnsupportedVersion(u16),
+    #[error(
+        "Node only speaks protocol versions {min_version} to {max_version}, none of which we support."
+    )]
+    IncompatiblePeer { min_version: u16, max_version: u16 },
+    #[error("No response to request {0:?} in time.")]
+    RequestTimeout(MsgId),
+    #[error("Node responded with an error: {0}")]
+    ErrorResponse(ErrorResponse),
     #[error("Failed to send msg {0:?}")]
     FailedSend(MsgId),
+    #[error("Msg {0:?} was not acked in time.")]
+    NotAcked(MsgId),
+    #[error("Sending msg {0:?} was cancelled.")]
+    Cancelled(MsgId),
     #[error("Serialisation error:: {0}")]
     SerialisationError(#[from] bincode::Error),
+    #[error("Transport error: {0}")]
+    Transport(#[from] TransportError),
 }
 
 impl From<qp2p::SendError> for Error {
@@ -36,3 +62,72 @@ impl From<qp2p::SendError> for Error {
         Self::AddressNotReachable(qp2p::RpcError::Send(error))
     }
 }
+
+impl Error {
+    /// The kind of failure, as told to the node whose msg ran into it.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ErrorResponse(response) => response.code,
+            Self::InvalidMsgReceived(_)
+            | Self::InvalidSignature(_)
+            | Self::Misdirected(_)
+            | Self::MessageTooLarge { .. }
+            | Self::InvalidHeader
+            | Self::UnsupportedVersion(_) => ErrorCode::InvalidRequest,
+            Self::ConnectingToUnknownNode(_)
+            | Self::CannotConnectEndpoint(_)
+            | Self::AddressNotReachable(_)
+            | Self::IncompatiblePeer { .. }
+            | Self::RequestTimeout(_)
+            | Self::FailedSend(_)
+            | Self::NotAcked(_)
+            | Self::Transport(_) => ErrorCode::NodeUnreachable,
+            Self::Cancelled(_) | Self::SerialisationError(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Why a msg could not be handled, sent back in place of the response to it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Error)]
+#[error("{code:?}: {reason}")]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    /// Human-readable account of the failure.
+    pub reason: String,
+    /// The nodes the msg was passed on to which failed it, if any.
+    pub failed_nodes: BTreeSet<NodeId>,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+            failed_nodes: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_failed_nodes(mut self, nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        self.failed_nodes.extend(nodes);
+        self
+    }
+}
+
+impl From<Error> for ErrorResponse {
+    fn from(error: Error) -> Self {
+        Self::new(error.code(), error.to_string())
+    }
+}
+
+/// The kinds of failure told apart in error responses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// The msg was invalid, or not one the node handles.
+    InvalidRequest,
+    /// A node the msg was passed on to could not be reached.
+    NodeUnreachable,
+    /// The nodes the msg was passed on to did not agree on the response.
+    QuorumDisagreed,
+    /// The node failed to handle the msg for reasons of its own.
+    Internal,
+}
@@ -0,0 +1,172 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, SECRET_KEY_LENGTH};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// The identity of a node: its Ed25519 public key.
+///
+/// Unlike its address, a node's id does not change over its lifetime.
+/// It is written as hex in human readable formats, such as the peers config file.
+#[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct NodeId([u8; 32]);
+
+impl NodeId {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Whether the signature over the bytes was made by the node with this id.
+    pub fn verify(&self, bytes: &[u8], signature: &Signature) -> bool {
+        VerifyingKey::from_bytes(&self.0)
+            .map(|key| key.verify_strict(bytes, signature).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+impl From<VerifyingKey> for NodeId {
+    fn from(key: VerifyingKey) -> Self {
+        Self(key.to_bytes())
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the first bytes are plenty to tell nodes apart in logs
+        write!(f, "NodeId({})", hex::encode(&self.0[..4]))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(s, &mut bytes)?;
+        Ok(Self(bytes))
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            s.parse().map_err(de::Error::custom)
+        } else {
+            <[u8; 32]>::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+/// The keypair a node identifies itself with.
+#[derive(Clone)]
+pub struct Keypair(SigningKey);
+
+impl Keypair {
+    pub fn random() -> Self {
+        Self(SigningKey::generate(&mut rand::rngs::OsRng))
+    }
+
+    /// Rebuilds a keypair from its hex encoded secret key.
+    pub fn from_hex(secret: &str) -> Result<Self, hex::FromHexError> {
+        let mut bytes = [0; SECRET_KEY_LENGTH];
+        hex::decode_to_slice(secret, &mut bytes)?;
+        Ok(Self::from_secret(bytes))
+    }
+
+    pub fn from_secret(secret: [u8; SECRET_KEY_LENGTH]) -> Self {
+        Self(SigningKey::from_bytes(&secret))
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.0.verifying_key().into()
+    }
+
+    pub fn sign(&self, bytes: &[u8]) -> Signature {
+        self.0.sign(bytes)
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never log the secret key
+        write!(f, "Keypair({:?})", self.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_written_as_hex_in_human_readable_formats_only() {
+        let id = Keypair::from_secret([1; 32]).id();
+        let hex = id.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<NodeId>(), Ok(id));
+        assert!(hex[2..].parse::<NodeId>().is_err());
+
+        let json = serde_json::to_string(&id).expect("Failed to serialise to json");
+        assert_eq!(json, format!("\"{hex}\""));
+        assert_eq!(serde_json::from_str::<NodeId>(&json).ok(), Some(id));
+
+        let bytes = bincode::serialize(&id).expect("Failed to serialise to bincode");
+        assert_eq!(bytes, id.as_bytes());
+        assert_eq!(bincode::deserialize::<NodeId>(&bytes).ok(), Some(id));
+    }
+
+    #[test]
+    fn signatures_verify_against_the_signer_and_bytes_only() {
+        let keypair = Keypair::from_secret([1; 32]);
+        let signature = keypair.sign(b"msg");
+        assert!(keypair.id().verify(b"msg", &signature));
+        assert!(!keypair.id().verify(b"another msg", &signature));
+        assert!(!Keypair::from_secret([2; 32])
+            .id()
+            .verify(b"msg", &signature));
+        // not every 32 bytes are a public key
+        assert!(!NodeId::from_bytes([0xff; 32]).verify(b"msg", &signature));
+    }
+
+    #[test]
+    fn keypairs_are_rebuilt_from_their_hex_secret() {
+        let keypair = Keypair::from_hex(&hex::encode([7; 32])).expect("A valid secret");
+        assert_eq!(keypair.id(), Keypair::from_secret([7; 32]).id());
+        assert!(Keypair::from_hex("07").is_err());
+        assert!(Keypair::from_hex(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn keypairs_never_show_their_secret() {
+        let keypair = Keypair::from_secret([7; 32]);
+        let debug = format!("{keypair:?}");
+        assert_eq!(debug, format!("Keypair({:?})", keypair.id()));
+        assert!(!debug.contains(&hex::encode([7; 4])));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::comms::{NetworkMsg, NetworkNode};
+use crate::comms::{Error, Keypair, NetworkMsg, NetworkNode, NodeId};
 
 // Copyright 2023 MaidSafe.net limited.
 //
@@ -7,29 +7,41 @@ use crate::comms::{NetworkMsg, NetworkNode};
 // under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
-use super::{CommEvent, MsgReceived, MsgTrait};
+use super::{
+    reliable::SeenMsgs,
+    transport::{Frame, IncomingConnection, IncomingConnections, SendStream},
+    wire::{self, FrameKind, Handshake, Header},
+    CommEvent, MsgReceived, MsgTrait, Responder,
+};
 
-use qp2p::{Connection, ConnectionIncoming, IncomingConnections};
+use std::sync::Arc;
 use tokio::{sync::mpsc::Sender, task};
 use tracing::{debug, error, trace, warn};
 
 #[tracing::instrument(skip_all)]
 pub(crate) fn listen_for_connections<T: MsgTrait + 'static>(
+    keypair: Keypair,
+    max_msg_size: u64,
     comm_events_sender: Sender<CommEvent<T>>,
     mut incoming_connections: IncomingConnections,
 ) {
+    // shared by the responders to all msgs
+    let keypair = Arc::new(keypair);
+    let seen = SeenMsgs::default();
     let _handle = task::spawn(async move {
-        while let Some((connection, incoming_msgs)) = incoming_connections.next().await {
+        while let Some(connection) = incoming_connections.recv().await {
             trace!(
                 "IncomingConnection: from {:?} with connection_id {}",
-                connection.remote_address(),
-                connection.id()
+                connection.remote_addr,
+                connection.id
             );
 
             let _handle = task::spawn(listen_for_msgs(
+                keypair.clone(),
+                max_msg_size,
+                seen.clone(),
                 comm_events_sender.clone(),
                 connection,
-                incoming_msgs,
             ));
         }
     });
@@ -37,16 +49,23 @@ pub(crate) fn listen_for_connections<T: MsgTrait + 'static>(
 
 #[tracing::instrument(skip_all)]
 pub(crate) async fn listen_for_msgs<T: MsgTrait>(
+    keypair: Arc<Keypair>,
+    max_msg_size: u64,
+    seen: SeenMsgs,
     comm_events: Sender<CommEvent<T>>,
-    conn: Connection,
-    mut incoming_msgs: ConnectionIncoming,
+    conn: IncomingConnection,
 ) {
-    let conn_id = conn.id();
-    let remote_address = conn.remote_address();
+    let our_id = keypair.id();
+    let IncomingConnection {
+        id: conn_id,
+        remote_addr: remote_address,
+        msgs: mut incoming_msgs,
+    } = conn;
 
-    while let Some(result) = incoming_msgs.next_with_stream().await.transpose() {
+    while let Some(result) = incoming_msgs.recv().await {
         match result {
-            Ok((msg_bytes, send_stream)) => {
+            Ok(msg) => {
+                let (frame, send_stream) = (msg.frame, msg.send_stream);
                 let stream_info = if let Some(stream) = &send_stream {
                     format!(" on {}", stream.id())
                 } else {
@@ -55,26 +74,86 @@ pub(crate) async fn listen_for_msgs<T: MsgTrait>(
                 debug!(
                     "New msg arrived over conn_id={conn_id} from {remote_address:?}{stream_info}"
                 );
-                // ignore safe network related stuff, just get the payload
-                let payload = msg_bytes.0 .2;
-                let wire_msg = match NetworkMsg::from_bytes(payload) {
-                    Ok(wire_msg) => wire_msg,
+                let header = match Header::from_bytes(&frame.header) {
+                    Ok(header) => header,
                     Err(error) => {
                         // TODO: should perhaps rather drop this connection.. as it is a spam vector
-                        debug!("Failed to deserialize message received from {remote_address:?}{stream_info}: {error:?}");
+                        debug!("Dropping frame from {remote_address:?}{stream_info}: {error}");
                         continue;
                     }
                 };
-
+                // the sender can't be verified without decoding the msg, so it is only as claimed until then
                 let src = NetworkNode {
+                    id: header.src,
                     addr: remote_address,
                 };
-                let msg_id = wire_msg.id;
+                let msg_id = header.msg_id;
+
+                let size = frame.len();
+                if size as u64 > max_msg_size {
+                    warn!("Msg {msg_id:?} of {size} bytes from {src:?}{stream_info} is too large");
+                    let error = Error::MessageTooLarge {
+                        size,
+                        max_size: max_msg_size,
+                    };
+                    report(src, error, &comm_events).await;
+                    continue;
+                }
+
+                match header.kind {
+                    FrameKind::Handshake => {
+                        answer_handshake(our_id, &frame, send_stream, src).await;
+                        continue;
+                    }
+                    FrameKind::Ack => {
+                        // acks come back on the streams of our reliable msgs, not on their own
+                        debug!("Dropping ack {msg_id:?} from {src:?}{stream_info}");
+                        continue;
+                    }
+                    FrameKind::Msg | FrameKind::ReliableMsg => {}
+                }
+                if !header.is_supported() {
+                    let error = Error::UnsupportedVersion(header.version);
+                    warn!("Dropping msg {msg_id:?} from {src:?}{stream_info}: {error}");
+                    continue;
+                }
+                match wire::dst_from_bytes(&frame.dst) {
+                    // a msg signed for someone else is being replayed to us, or was misrouted
+                    Ok(Some(dst)) if dst != our_id => {
+                        warn!("Msg {msg_id:?} from {src:?}{stream_info} is meant for {dst:?}");
+                        report(src, Error::Misdirected(msg_id), &comm_events).await;
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        debug!("Dropping msg {msg_id:?} from {src:?}{stream_info}: {error}");
+                        continue;
+                    }
+                }
+
+                let wire_msg = match NetworkMsg::from_frame(frame, max_msg_size) {
+                    Ok(wire_msg) => wire_msg,
+                    Err(error) => {
+                        debug!("Failed to deserialize message received from {remote_address:?}{stream_info}: {error:?}");
+                        continue;
+                    }
+                };
+                if let Err(error) = wire_msg.verify() {
+                    warn!("Msg {msg_id:?} from {src:?}{stream_info} failed verification");
+                    report(src, error, &comm_events).await;
+                    continue;
+                }
                 debug!(
                         "Msg {msg_id:?} received, over conn_id={conn_id}, from: {src:?}{stream_info} was: {wire_msg:?}"
                     );
 
-                msg_received(wire_msg, src, send_stream, comm_events.clone()).await;
+                if header.kind == FrameKind::ReliableMsg {
+                    receive_reliably(our_id, wire_msg, src, send_stream, &seen, &comm_events).await;
+                    continue;
+                }
+                let responder = send_stream
+                    .map(|stream| Responder::new(keypair.clone(), wire_msg.id, src, stream));
+                msg_received(wire_msg, src, responder, comm_events.clone()).await;
             }
             Err(error) => {
                 warn!("Error on connection {conn_id} with {remote_address}: {error:?}");
@@ -85,17 +164,97 @@ pub(crate) async fn listen_for_msgs<T: MsgTrait>(
     trace!(%conn_id, %remote_address, "ConnectionClosed");
 }
 
+/// Hands the reliable msg on, unless a copy of it already was, and acks it.
+async fn receive_reliably<T: MsgTrait>(
+    our_id: NodeId,
+    wire_msg: NetworkMsg<T>,
+    src: NetworkNode,
+    send_stream: Option<SendStream>,
+    seen: &SeenMsgs,
+    comm_events: &Sender<CommEvent<T>>,
+) {
+    let msg_id = wire_msg.id;
+    let stream = match send_stream {
+        Some(stream) => stream,
+        None => {
+            debug!("Dropping reliable msg {msg_id:?} from {src:?}, which came without a stream to ack on");
+            return;
+        }
+    };
+    // the copies are acked all the same, in case the ack of the first one was lost
+    if seen.insert(wire_msg.src, msg_id) {
+        msg_received(wire_msg, src, None, comm_events.clone()).await;
+    } else {
+        trace!("Msg {msg_id:?} from {src:?} was already received, acking it again");
+    }
+    if let Err(error) = stream.send(wire::ack(msg_id, our_id, src.id)).await {
+        debug!("Failed to ack {msg_id:?} to {src:?}: {error}");
+    }
+}
+
+/// Tells the node which opened the connection the protocol version to use over it, if any.
+async fn answer_handshake(
+    our_id: NodeId,
+    frame: &Frame,
+    send_stream: Option<SendStream>,
+    src: NetworkNode,
+) {
+    let (min_version, max_version) = match Handshake::from_frame(frame) {
+        Ok(Handshake::Hello {
+            min_version,
+            max_version,
+        }) => (min_version, max_version),
+        _ => {
+            debug!("Dropping invalid handshake from {src:?}");
+            return;
+        }
+    };
+    let answer = Handshake::answer(min_version, max_version);
+    if let Handshake::Refuse { .. } = answer {
+        warn!(
+            "Refusing {src:?}, which only speaks protocol versions {min_version} to {max_version}"
+        );
+    }
+    let stream = match send_stream {
+        Some(stream) => stream,
+        None => {
+            debug!("Handshake from {src:?} came without a stream to answer on");
+            return;
+        }
+    };
+    let frame = match answer.to_frame(our_id, Some(src.id)) {
+        Ok(frame) => frame,
+        Err(error) => {
+            error!("Failed to encode our handshake answer: {error:?}");
+            return;
+        }
+    };
+    if let Err(error) = stream.send(frame).await {
+        debug!("Failed to answer the handshake of {src:?}: {error}");
+    }
+}
+
+/// Reports an issue with a msg received from the node.
+async fn report<T: MsgTrait>(src: NetworkNode, error: Error, comm_events: &Sender<CommEvent<T>>) {
+    let _ = comm_events
+        .send(CommEvent::Error {
+            node_id: src,
+            error,
+        })
+        .await;
+}
+
 pub(crate) async fn msg_received<T: MsgTrait>(
     wire_msg: NetworkMsg<T>,
     sender: NetworkNode,
-    send_stream: Option<qp2p::SendStream>,
+    responder: Option<Responder<T>>,
     comm_events: Sender<CommEvent<T>>,
 ) {
     let msg_id = wire_msg.id;
     let msg_event = CommEvent::Msg(MsgReceived {
-        sender: sender.addr,
+        sender,
         wire_msg,
-        send_stream,
+        responder,
     });
 
     // handle the message first
@@ -7,71 +7,274 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 mod error;
+mod identity;
 mod listener;
 mod node_link;
+mod pending;
+mod quorum;
+mod reliable;
+mod responder;
+mod retry;
+mod transport;
+pub mod wire;
+
+pub use self::error::{Error, ErrorCode, ErrorResponse, Result};
+pub use self::identity::{Keypair, NodeId};
+pub use self::quorum::{Quorum, QuorumResponse};
+pub use self::responder::Responder;
+pub use self::retry::{RetryPolicy, RetryStats};
+pub use self::transport::{
+    Connection, Frame, IncomingConnection, IncomingConnections, IncomingMsg, MemoryNetwork,
+    MemoryTransport, NetworkFaults, Qp2pTransport, ResponseStream, SendStream, Transport,
+    TransportError,
+};
+pub use self::wire::{HEADER_LEN, MAGIC, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
-pub use self::error::{Error, Result};
-
-use self::node_link::NodeLink;
+use self::node_link::{NodeLink, NodeLinkError};
+use self::pending::{PendingSend, PendingSends};
+use self::reliable::RetransmitBuffer;
+use self::responder::PendingRequest;
+use self::retry::RetryCounters;
+use self::wire::{FrameKind, Header};
 
+use bincode::Options;
 use bytes::Bytes;
 use custom_debug::Debug;
-use futures::future::join_all;
-use qp2p::{Endpoint, SendStream};
+use ed25519_dalek::Signature;
+use futures::{
+    future,
+    stream::{FuturesUnordered, StreamExt},
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    hash::{Hash, Hasher},
     net::SocketAddr,
+    sync::Arc,
 };
 use tokio::{
-    sync::mpsc::{self, Receiver, Sender},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot,
+    },
     task,
+    time::{sleep_until, timeout_at, Duration, Instant},
 };
 use tracing::{debug, error, trace, warn};
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+/// Largest msg we accept by default, so that a malformed length prefix
+/// can't make us allocate more than that.
+pub const DEFAULT_MAX_MSG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// How long we wait for the response to a request by default.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long we keep trying to get a msg through by default.
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long we wait for the ack of a reliable msg by default, before sending it again.
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct MsgId(u64);
 pub trait MsgTrait:
-    Default + std::marker::Send + Clone + std::fmt::Debug + Serialize + for<'a> Deserialize<'a>
+    Default
+    + std::marker::Send
+    + Clone
+    + PartialEq
+    + std::fmt::Debug
+    + Serialize
+    + for<'a> Deserialize<'a>
 {
 }
 
+impl Default for MsgId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MsgId {
     /// Generates a new `MsgId` with random content.
     pub fn new() -> Self {
         Self(rand::random())
     }
+
+    /// The `MsgId` of a msg with the given content, the same each time the msg is sent again,
+    /// so that its copies are known as such. It only stays the same within a run of the node.
+    pub fn of(content: &impl Hash) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Self(hasher.finish())
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NetworkMsg<T> {
     pub id: MsgId,
-    pub payload: T,
+    /// Id of the node which sent the msg.
+    pub src: NodeId,
+    /// Id of the node the msg is meant for, signed along with the payload,
+    /// so that the msg can't be replayed to other nodes.
+    pub dst: Option<NodeId>,
+    /// The payload, or, in a response, why the msg responded to could not be handled.
+    /// Errors are boxed, so that they don't weigh on the size of every msg.
+    pub body: Result<T, Box<ErrorResponse>>,
+    /// Signature of the sender over the msg and the header of its frame,
+    /// proving the msg comes from `src`, as sent.
+    pub signature: Option<Signature>,
+    /// Whether the msg is to be acked by its recipient, which the kind of its frame tells.
+    #[serde(skip)]
+    pub reliable: bool,
 }
 
 impl<T: MsgTrait> NetworkMsg<T> {
     pub fn from_bytes(value: Bytes) -> Result<Self> {
-        Ok(bincode::deserialize(&value)?)
+        Self::from_bytes_with_limit(value, DEFAULT_MAX_MSG_SIZE)
+    }
+
+    /// Decodes a msg of at most `max_size` bytes, allocating no more than that while at it.
+    pub fn from_bytes_with_limit(value: Bytes, max_size: u64) -> Result<Self> {
+        if value.len() as u64 > max_size {
+            return Err(Error::MessageTooLarge {
+                size: value.len(),
+                max_size,
+            });
+        }
+        Ok(wire_options().with_limit(max_size).deserialize(&value)?)
     }
 
     pub fn to_bytes(&self) -> Result<Bytes> {
-        Ok(bincode::serialize(self)?.into())
+        Ok(wire_options().serialize(self)?.into())
     }
 
-    pub fn error_msg() -> NetworkMsg<T> {
-        NetworkMsg {
-            id: MsgId::new(),
-            payload: Default::default(),
+    /// Decodes a msg from a frame of a version we support,
+    /// checking that the header and destination of the frame are the msg's.
+    pub fn from_frame(frame: Frame, max_size: u64) -> Result<Self> {
+        let header = Header::from_bytes(&frame.header)?;
+        if !header.is_msg() {
+            return Err(Error::InvalidHeader);
         }
+        if !header.is_supported() {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        let dst = wire::dst_from_bytes(&frame.dst)?;
+        let mut msg = Self::from_bytes_with_limit(frame.payload, max_size)?;
+        if msg.id != header.msg_id || msg.src != header.src || msg.dst != dst {
+            return Err(Error::InvalidHeader);
+        }
+        msg.reliable = header.kind == FrameKind::ReliableMsg;
+        Ok(msg)
+    }
+
+    /// Encodes the msg in our protocol version, its id, sender and destination going in the header.
+    pub fn to_frame(&self) -> Result<Frame> {
+        Ok(Frame {
+            header: Header::ours(self.kind(), self.id, self.src).to_bytes(),
+            dst: wire::dst_to_bytes(self.dst),
+            payload: self.to_bytes()?,
+        })
+    }
+
+    /// A msg from us to `dst`, signed with our keypair.
+    pub fn signed(keypair: &Keypair, id: MsgId, dst: NodeId, payload: T) -> Result<Self> {
+        Self::signed_body(keypair, id, dst, Ok(payload), false)
+    }
+
+    /// A msg from us to `dst`, signed with our keypair, to be acked once received.
+    pub fn signed_reliable(keypair: &Keypair, id: MsgId, dst: NodeId, payload: T) -> Result<Self> {
+        Self::signed_body(keypair, id, dst, Ok(payload), true)
+    }
+
+    /// A response from us to `dst`, telling it why its msg `id` could not be handled.
+    pub fn signed_error(
+        keypair: &Keypair,
+        id: MsgId,
+        dst: NodeId,
+        error: ErrorResponse,
+    ) -> Result<Self> {
+        Self::signed_body(keypair, id, dst, Err(Box::new(error)), false)
+    }
+
+    fn signed_body(
+        keypair: &Keypair,
+        id: MsgId,
+        dst: NodeId,
+        body: Result<T, Box<ErrorResponse>>,
+        reliable: bool,
+    ) -> Result<Self> {
+        let mut msg = NetworkMsg {
+            id,
+            src: keypair.id(),
+            dst: Some(dst),
+            body,
+            signature: None,
+            reliable,
+        };
+        msg.signature = Some(keypair.sign(&msg.signed_bytes()?));
+        Ok(msg)
+    }
+
+    /// The payload, or the error responded with.
+    pub fn into_payload(self) -> Result<T> {
+        self.body.map_err(|error| Error::ErrorResponse(*error))
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// Checks that a signed msg was signed by its `src`.
+    /// Unsigned msgs pass, it is up to the upper layers whether to trust them.
+    pub fn verify(&self) -> Result<()> {
+        let signature = match &self.signature {
+            Some(signature) => signature,
+            None => return Ok(()),
+        };
+        if self.src.verify(&self.signed_bytes()?, signature) {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature(self.id))
+        }
+    }
+
+    /// Whether the msg is meant for the node with the given id, msgs without a `dst` being for anyone.
+    pub fn is_for(&self, id: &NodeId) -> bool {
+        self.dst.is_none_or(|dst| dst == *id)
+    }
+
+    fn kind(&self) -> FrameKind {
+        if self.reliable {
+            FrameKind::ReliableMsg
+        } else {
+            FrameKind::Msg
+        }
+    }
+
+    /// What the signature is over: the whole msg but the signature,
+    /// along with the version and kind of the frame, which are not part of the msg.
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        let header = (PROTOCOL_VERSION, self.kind().tag());
+        let msg = (&self.id, &self.src, &self.dst, &self.body);
+        Ok(bincode::serialize(&(header, msg))?)
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct NetworkNode {
-    /// Network participant address
+    /// Network participant identity
+    pub id: NodeId,
+    /// Network participant current address, which may change over time
     pub addr: SocketAddr,
 }
 
+/// The bincode encoding of msgs on the wire: the same as `bincode::serialize`.
+fn wire_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
 /// Standard channel size, to allow for large swings in throughput
 static STANDARD_CHANNEL_SIZE: usize = 100_000;
 
@@ -92,13 +295,101 @@ pub enum CommEvent<T> {
 /// A msg received on the wire.
 #[derive(Debug)]
 pub struct MsgReceived<T> {
-    /// The socketaddr of sender of the msg.
-    pub sender: SocketAddr,
+    /// The sender of the msg, as identified in the msg, at the address it came from.
+    pub sender: NetworkNode,
     /// The msg that we received.
     pub wire_msg: NetworkMsg<T>,
-    /// An optional stream to return msgs on, if
-    /// this msg came on a bidi-stream.
-    pub send_stream: Option<SendStream>,
+    /// Responds to the msg, if it came on a bidi-stream.
+    pub responder: Option<Responder<T>>,
+}
+
+/// Settings of the comm module.
+#[derive(Clone, Copy, Debug)]
+pub struct CommConfig {
+    /// Largest msg we accept, in bytes. Larger ones are dropped before being decoded.
+    pub max_msg_size: u64,
+    /// How long we wait for the response to a msg sent on a bidi-stream, unless told otherwise.
+    pub request_timeout: Duration,
+    /// How long we keep trying to send a msg which expects no response, unless told otherwise.
+    pub send_timeout: Duration,
+    /// How long we wait for the ack of a reliable msg, before sending it again.
+    pub ack_timeout: Duration,
+    /// How failed sends are retried, unless told otherwise.
+    pub retry: RetryPolicy,
+}
+
+impl Default for CommConfig {
+    fn default() -> Self {
+        Self {
+            max_msg_size: DEFAULT_MAX_MSG_SIZE,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+/// Settings of a single send, overriding those of the `CommConfig`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SendOptions {
+    /// When to give up on the msg, and on any response to it.
+    pub deadline: Option<Instant>,
+    /// How to retry the msg, should sending it fail.
+    pub retry: Option<RetryPolicy>,
+    /// Whether the msg is to be sent until acked by its recipient, which hands it on only once.
+    pub reliable: bool,
+}
+
+impl SendOptions {
+    /// Gives up on the msg at the deadline.
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Default::default()
+        }
+    }
+
+    /// Retries the msg as the policy tells.
+    pub fn with_retry(retry: RetryPolicy) -> Self {
+        Self {
+            retry: Some(retry),
+            ..Default::default()
+        }
+    }
+
+    /// Sends the msg until acked.
+    pub fn reliable() -> Self {
+        Self {
+            reliable: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Handle to a msg being fanned out, the responses to which are still outstanding.
+///
+/// Dropping the handle cancels the fan-out, and whatever requests are still in flight.
+#[must_use = "dropping the handle cancels the fan-out"]
+#[derive(Debug)]
+pub struct FanOut {
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+impl FanOut {
+    /// Lets the fan-out run to its end, or its deadline, without holding on to the handle.
+    pub fn detach(mut self) {
+        // dropped without a word, the fan-out is not cancelled
+        let _ = self.cancel.take();
+    }
+}
+
+impl Drop for FanOut {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+    }
 }
 
 /// Communication component of the node to interact with other nodes.
@@ -107,21 +398,49 @@ pub struct MsgReceived<T> {
 /// in the section (otherwise ignoring failed send to out of section nodes or clients).
 #[derive(Clone, Debug)]
 pub struct Comm {
-    our_endpoint: Endpoint,
+    keypair: Keypair,
+    config: CommConfig,
+    transport: Arc<dyn Transport>,
     cmd_sender: Sender<CommCmd>,
+    retry_counters: Arc<RetryCounters>,
+    retransmits: RetransmitBuffer,
+    pending: PendingSends,
 }
 
 impl Comm {
-    /// Creates a new instance of Comm with an endpoint
+    /// Creates a new instance of Comm with a qp2p endpoint
     /// and starts listening to the incoming messages from other nodes.
-    #[tracing::instrument(skip_all)]
     pub fn new<T: MsgTrait + 'static>(
+        keypair: Keypair,
         local_addr: SocketAddr,
     ) -> Result<(Self, Receiver<CommEvent<T>>)> {
-        let (our_endpoint, incoming_conns) = Endpoint::builder()
-            .addr(local_addr)
-            .server()?;
+        let (transport, incoming_conns) = Qp2pTransport::new(local_addr)?;
+        Ok(Self::with_transport(
+            keypair,
+            Arc::new(transport),
+            incoming_conns,
+        ))
+    }
 
+    /// Creates a new instance of Comm over the passed in transport
+    /// and starts listening to the incoming messages from other nodes.
+    pub fn with_transport<T: MsgTrait + 'static>(
+        keypair: Keypair,
+        transport: Arc<dyn Transport>,
+        incoming_conns: IncomingConnections,
+    ) -> (Self, Receiver<CommEvent<T>>) {
+        Self::with_config(keypair, transport, incoming_conns, CommConfig::default())
+    }
+
+    /// Creates a new instance of Comm over the passed in transport, with the given settings,
+    /// and starts listening to the incoming messages from other nodes.
+    #[tracing::instrument(skip_all)]
+    pub fn with_config<T: MsgTrait + 'static>(
+        keypair: Keypair,
+        transport: Arc<dyn Transport>,
+        incoming_conns: IncomingConnections,
+        config: CommConfig,
+    ) -> (Self, Receiver<CommEvent<T>>) {
         trace!("Creating comms..");
         // comm_events_receiver will be used by upper layer to receive all msgs coming in from the network
         // capacity of one as we limit w/ how many cmds we process in the upper layers.
@@ -129,29 +448,99 @@ impl Comm {
         // (we may want some buffer here?)
         let (comm_events_sender, comm_events_receiver) = mpsc::channel(1);
         let (cmd_sender, cmd_receiver) = mpsc::channel(STANDARD_CHANNEL_SIZE);
+        let retry_counters = Arc::new(RetryCounters::default());
+        let retransmits = RetransmitBuffer::default();
 
         // listen for msgs/connections to our endpoint
-        listener::listen_for_connections(comm_events_sender.clone(), incoming_conns);
-
-        process_cmds(our_endpoint.clone(), cmd_receiver, comm_events_sender);
-
-        Ok((
+        listener::listen_for_connections(
+            keypair.clone(),
+            config.max_msg_size,
+            comm_events_sender.clone(),
+            incoming_conns,
+        );
+
+        process_cmds(
+            keypair.id(),
+            config,
+            transport.clone(),
+            retry_counters.clone(),
+            retransmits.clone(),
+            cmd_receiver,
+            comm_events_sender,
+        );
+
+        (
             Self {
-                our_endpoint,
+                keypair,
+                config,
+                transport,
                 cmd_sender,
+                retry_counters,
+                retransmits,
+                pending: PendingSends::default(),
             },
             comm_events_receiver,
-        ))
+        )
     }
 
     /// The socket address of our endpoint.
     pub fn socket_addr(&self) -> SocketAddr {
-        self.our_endpoint.local_addr()
+        self.transport.local_addr()
+    }
+
+    /// Our identity and address on the network.
+    pub fn our_node(&self) -> NetworkNode {
+        NetworkNode {
+            id: self.keypair.id(),
+            addr: self.socket_addr(),
+        }
+    }
+
+    /// How many attempts at sending msgs were made and retried so far.
+    pub fn retry_stats(&self) -> RetryStats {
+        self.retry_counters.stats()
+    }
+
+    /// The msgs sent reliably which are yet to be acked.
+    pub fn unacked(&self) -> BTreeSet<MsgId> {
+        self.retransmits.ids()
+    }
+
+    /// The keypair we identify ourselves and sign our msgs with.
+    pub fn keypair(&self) -> &Keypair {
+        &self.keypair
+    }
+
+    /// Builds a msg from us to `dst`, signed so that the recipient can tell it is genuine.
+    pub fn signed_msg<T: MsgTrait>(
+        &self,
+        id: MsgId,
+        dst: NodeId,
+        payload: T,
+    ) -> Result<NetworkMsg<T>> {
+        NetworkMsg::signed(&self.keypair, id, dst, payload)
+    }
+
+    /// Builds a msg from us to `dst`, signed so that the recipient can tell it is genuine,
+    /// to be sent with reliable `SendOptions`.
+    pub fn signed_reliable_msg<T: MsgTrait>(
+        &self,
+        id: MsgId,
+        dst: NodeId,
+        payload: T,
+    ) -> Result<NetworkMsg<T>> {
+        NetworkMsg::signed_reliable(&self.keypair, id, dst, payload)
+    }
+
+    /// Waits for the msgs sent with `send_out_frame` so far to be sent, or given up on,
+    /// and for the reliable ones among them to be acked.
+    pub async fn flush(&self) {
+        self.pending.flushed().await
     }
 
     /// Closes the endpoint.
     pub fn close_endpoint(&self) {
-        self.our_endpoint.close()
+        self.transport.close()
     }
 
     /// Sets the available targets to be only those in the passed in set.
@@ -162,41 +551,147 @@ impl Comm {
         self.send_cmd(CommCmd::SetTargets(targets))
     }
 
-    /// Sends the payload on a new or existing connection.
-    #[tracing::instrument(skip(self, bytes))]
-    pub fn send_out_bytes(&self, node_id: NetworkNode, msg_id: MsgId, bytes: Bytes) {
+    /// Sends the payload on a new or existing connection,
+    /// giving up at the deadline of the options, or after the configured send timeout.
+    ///
+    /// A reliable msg, signed as such, is kept until the node acks it, and sent again each time
+    /// no ack came within the configured ack timeout. If it is still unacked at the deadline,
+    /// a `NotAcked` error is reported.
+    /// A reliable msg sent again under the same id while still unacked is not sent twice.
+    #[tracing::instrument(skip(self, frame))]
+    pub fn send_out_frame(
+        &self,
+        node_id: NetworkNode,
+        msg_id: MsgId,
+        frame: Frame,
+        options: SendOptions,
+    ) {
+        let pending = self.pending.start();
+        if options.reliable {
+            if !self.retransmits.insert(msg_id, node_id, frame, pending) {
+                trace!("Msg {msg_id:?} to {node_id:?} is still unacked, it is already being sent");
+                return;
+            }
+            return self.send_cmd(CommCmd::SendReliably {
+                msg_id,
+                node_id,
+                deadline: self.deadline(options, self.config.send_timeout),
+                retry: self.retry(options),
+            });
+        }
         self.send_cmd(CommCmd::Send {
             msg_id,
             node_id,
-            bytes,
+            frame,
+            deadline: self.deadline(options, self.config.send_timeout),
+            retry: self.retry(options),
+            pending,
         })
     }
 
-    /// Sends the payload on a new bidi-stream and pushes the response onto the comm event channel.
-    #[tracing::instrument(skip(self, bytes))]
-    pub fn send_and_return_response(&self, node_id: NetworkNode, msg_id: MsgId, bytes: Bytes) {
+    /// Sends the payload on a new bidi-stream and pushes the response onto the comm event channel,
+    /// giving up at the deadline of the options, or after the configured request timeout.
+    #[tracing::instrument(skip(self, frame))]
+    pub fn send_and_return_response(
+        &self,
+        node_id: NetworkNode,
+        msg_id: MsgId,
+        frame: Frame,
+        options: SendOptions,
+    ) {
         self.send_cmd(CommCmd::SendAndReturnResponse {
             msg_id,
             node_id,
-            bytes,
+            frame,
+            deadline: self.deadline(options, self.config.request_timeout),
+            retry: self.retry(options),
         })
     }
 
-    /// Sends the payload on new bidi-stream to noe and sends the response on the dst stream.
-    #[tracing::instrument(skip(self, node_bytes))]
-    pub fn send_and_respond_on_stream(
+    /// Sends the msg to the node on a new bidi-stream, and waits for its response,
+    /// giving up at the deadline of the options, or after the configured request timeout.
+    ///
+    /// The response must be signed by the node, for us, and carry the id of the request.
+    /// The reliable flag of the options is moot: the response tells the request got through.
+    pub async fn request<Req: MsgTrait, Resp: MsgTrait>(
+        &self,
+        node_id: NetworkNode,
+        msg: Req,
+        options: SendOptions,
+    ) -> Result<Resp> {
+        let msg_id = MsgId::new();
+        let frame = self.signed_msg(msg_id, node_id.id, msg)?.to_frame()?;
+        let deadline = self.deadline(options, self.config.request_timeout);
+        let (response_sender, response) = oneshot::channel();
+        self.cmd_sender
+            .send(CommCmd::Request {
+                msg_id,
+                node_id,
+                frame,
+                deadline,
+                retry: self.retry(options),
+                response: response_sender,
+            })
+            .await
+            .map_err(|_| Error::FailedSend(msg_id))?;
+
+        let frame = match timeout_at(deadline, response).await {
+            Ok(Ok(result)) => result?,
+            // the cmd loop is gone
+            Ok(Err(_)) => return Err(Error::FailedSend(msg_id)),
+            Err(_) => return Err(Error::RequestTimeout(msg_id)),
+        };
+        let response = NetworkMsg::<Resp>::from_frame(frame, self.config.max_msg_size)?;
+        response.verify()?;
+        // anyone else's msg, or the response to another request, is no answer to this one
+        let is_answer = response.src == node_id.id
+            && response.dst == Some(self.keypair.id())
+            && response.id == msg_id;
+        if !is_answer {
+            return Err(Error::InvalidMsgReceived(msg_id));
+        }
+        response.into_payload()
+    }
+
+    /// Sends the payloads on new bidi-streams to the nodes, and responds with the response the quorum
+    /// agrees on, as a `QuorumResponse`, or with an `ErrorResponse` telling why there is none.
+    ///
+    /// The nodes yet to respond at the deadline of the options, or after the configured request
+    /// timeout, are taken to be unreachable. Dropping the returned handle cancels the fan-out.
+    #[tracing::instrument(skip(self, node_frames))]
+    pub fn send_and_respond_on_stream<T: MsgTrait>(
         &self,
         msg_id: MsgId,
-        node_bytes: BTreeMap<NetworkNode, Bytes>,
+        node_frames: BTreeMap<NetworkNode, Frame>,
         expected_targets: usize,
-        dst_stream: (NetworkNode, SendStream),
-    ) {
+        quorum: Quorum,
+        responder: Responder<T>,
+        options: SendOptions,
+    ) -> FanOut {
+        let (cancel, cancelled) = oneshot::channel();
         self.send_cmd(CommCmd::SendAndRespondOnStream {
             msg_id,
-            node_bytes,
+            node_frames,
             expected_targets,
-            dst_stream,
-        })
+            quorum,
+            request: responder.into_request(),
+            deadline: self.deadline(options, self.config.request_timeout),
+            retry: self.retry(options),
+            cancelled,
+        });
+        FanOut {
+            cancel: Some(cancel),
+        }
+    }
+
+    /// The deadline of the options, if any, else the one `timeout` from now.
+    fn deadline(&self, options: SendOptions, timeout: Duration) -> Instant {
+        options.deadline.unwrap_or_else(|| Instant::now() + timeout)
+    }
+
+    /// The retry policy of the options, if any, else the configured one.
+    fn retry(&self, options: SendOptions) -> RetryPolicy {
+        options.retry.unwrap_or(self.config.retry)
     }
 
     fn send_cmd(&self, cmd: CommCmd) {
@@ -217,31 +712,66 @@ enum CommCmd {
         msg_id: MsgId,
         node_id: NetworkNode,
         #[debug(skip)]
-        bytes: Bytes,
+        frame: Frame,
+        deadline: Instant,
+        retry: RetryPolicy,
+        #[debug(skip)]
+        pending: PendingSend,
     },
     SetTargets(BTreeSet<NetworkNode>),
+    /// Sends the msg kept in the retransmit buffer until acked.
+    SendReliably {
+        msg_id: MsgId,
+        node_id: NetworkNode,
+        deadline: Instant,
+        retry: RetryPolicy,
+    },
     SendAndReturnResponse {
         node_id: NetworkNode,
         msg_id: MsgId,
         #[debug(skip)]
-        bytes: Bytes,
+        frame: Frame,
+        deadline: Instant,
+        retry: RetryPolicy,
     },
     SendAndRespondOnStream {
         msg_id: MsgId,
         #[debug(skip)]
-        node_bytes: BTreeMap<NetworkNode, Bytes>,
+        node_frames: BTreeMap<NetworkNode, Frame>,
         expected_targets: usize,
-        dst_stream: (NetworkNode, SendStream),
+        quorum: Quorum,
+        request: PendingRequest,
+        deadline: Instant,
+        retry: RetryPolicy,
+        #[debug(skip)]
+        cancelled: oneshot::Receiver<()>,
+    },
+    Request {
+        msg_id: MsgId,
+        node_id: NetworkNode,
+        #[debug(skip)]
+        frame: Frame,
+        deadline: Instant,
+        retry: RetryPolicy,
+        #[debug(skip)]
+        response: oneshot::Sender<Result<Frame>>,
     },
 }
 
 fn process_cmds<T: MsgTrait + 'static>(
-    our_endpoint: Endpoint,
+    our_id: NodeId,
+    config: CommConfig,
+    transport: Arc<dyn Transport>,
+    retry_counters: Arc<RetryCounters>,
+    retransmits: RetransmitBuffer,
     mut cmd_receiver: Receiver<CommCmd>,
     comm_events: Sender<CommEvent<T>>,
 ) {
     let _handle = task::spawn(async move {
+        let new_link =
+            |node_id| NodeLink::new(our_id, node_id, transport.clone(), retry_counters.clone());
         let mut links = BTreeMap::<NetworkNode, NodeLink>::new();
+        let max_msg_size = config.max_msg_size;
         while let Some(cmd) = cmd_receiver.recv().await {
             trace!("Comms cmd handling: {cmd:?}");
             match cmd {
@@ -251,53 +781,121 @@ fn process_cmds<T: MsgTrait + 'static>(
                     links.retain(|p, _| targets.contains(p));
                     // Adds new links for each new target.
                     targets.iter().for_each(|node_id| {
-                        if links.get(node_id).is_none() {
-                            let link = NodeLink::new(*node_id, our_endpoint.clone());
-                            let _ = links.insert(*node_id, link);
+                        if !links.contains_key(node_id) {
+                            let _ = links.insert(*node_id, new_link(*node_id));
                         }
                     });
                 }
                 CommCmd::Send {
                     msg_id,
                     node_id,
-                    bytes,
+                    frame,
+                    deadline,
+                    retry,
+                    pending,
                 } => {
                     // add sender to targets (TODO check if thats ok)
-                    let link = NodeLink::new(node_id, our_endpoint.clone());
-                    let _ = links.insert(node_id, link);
+                    // keeping any existing link, so that its connections get reused
+                    let _ = links.entry(node_id).or_insert_with(|| new_link(node_id));
 
                     if let Some(link) = get_link(msg_id, node_id, &links, comm_events.clone()) {
-                        send(msg_id, link, bytes, comm_events.clone())
+                        send(
+                            msg_id,
+                            link,
+                            frame,
+                            deadline,
+                            retry,
+                            pending,
+                            comm_events.clone(),
+                        )
                     }
                 }
+                CommCmd::SendReliably {
+                    msg_id,
+                    node_id,
+                    deadline,
+                    retry,
+                } => {
+                    // keeping any existing link, so that its connections get reused
+                    let link = links
+                        .entry(node_id)
+                        .or_insert_with(|| new_link(node_id))
+                        .clone();
+                    send_reliably(
+                        msg_id,
+                        link,
+                        deadline,
+                        retry,
+                        config.ack_timeout,
+                        retransmits.clone(),
+                        comm_events.clone(),
+                    )
+                }
+                CommCmd::Request {
+                    msg_id,
+                    node_id,
+                    frame,
+                    deadline,
+                    retry,
+                    response,
+                } => {
+                    // keeping any existing link, so that its connections get reused
+                    let link = links
+                        .entry(node_id)
+                        .or_insert_with(|| new_link(node_id))
+                        .clone();
+                    request(msg_id, link, frame, deadline, retry, response)
+                }
                 CommCmd::SendAndReturnResponse {
                     node_id,
                     msg_id,
-                    bytes,
+                    frame,
+                    deadline,
+                    retry,
                 } => {
                     if let Some(link) = get_link(msg_id, node_id, &links, comm_events.clone()) {
-                        send_and_return_response(msg_id, link, bytes, comm_events.clone())
+                        send_and_return_response(
+                            msg_id,
+                            link,
+                            frame,
+                            deadline,
+                            retry,
+                            max_msg_size,
+                            comm_events.clone(),
+                        )
                     }
                 }
                 CommCmd::SendAndRespondOnStream {
                     msg_id,
-                    node_bytes,
+                    node_frames,
                     expected_targets,
-                    dst_stream,
+                    quorum,
+                    request,
+                    deadline,
+                    retry,
+                    cancelled,
                 } => {
-                    let node_bytes = node_bytes
+                    let node_frames = node_frames
                         .into_iter()
-                        .map(|(node_id, bytes)| {
+                        .map(|(node_id, frame)| {
                             let link = get_link(msg_id, node_id, &links, comm_events.clone());
-                            (node_id, (link, bytes))
+                            (node_id, (link, frame))
                         })
                         .collect();
 
+                    let settle = Settle {
+                        quorum,
+                        expected_targets,
+                        deadline,
+                        retry,
+                        cancelled,
+                    };
                     send_and_respond_on_stream(
                         msg_id,
-                        node_bytes,
-                        expected_targets,
-                        dst_stream,
+                        node_frames,
+                        settle,
+                        max_msg_size,
+                        Responder::from_request(request),
                         comm_events.clone(),
                     )
                 }
@@ -317,7 +915,11 @@ fn get_link<T: MsgTrait + 'static>(
         Some(link) => Some(link.clone()),
         None => {
             error!("Sending message (msg_id: {msg_id:?}) to {node_id:?} failed: unknown node.");
-            send_error(node_id, Error::ConnectingToUnknownNode(node_id), comm_events);
+            send_error(
+                node_id,
+                Error::ConnectingToUnknownNode(node_id),
+                comm_events,
+            );
             None
         }
     }
@@ -326,53 +928,189 @@ fn get_link<T: MsgTrait + 'static>(
 #[tracing::instrument(skip_all)]
 fn send<T: MsgTrait + 'static>(
     msg_id: MsgId,
-    mut link: NodeLink,
-    bytes: Bytes,
+    link: NodeLink,
+    frame: Frame,
+    deadline: Instant,
+    retry: RetryPolicy,
+    pending: PendingSend,
     comm_events: Sender<CommEvent<T>>,
 ) {
     let _handle = task::spawn(async move {
-        let bytes_len = bytes.len();
+        // counted out once sent or given up on
+        let _pending = pending;
+        let frame_len = frame.len();
         let node_id = link.node();
-        trace!("Sending message bytes ({bytes_len} bytes) w/ {msg_id:?} to {node_id:?}");
-        match link.send(msg_id, bytes).await {
-            Ok(()) => {
+        trace!("Sending message frame ({frame_len} bytes) w/ {msg_id:?} to {node_id:?}");
+        match timeout_at(deadline, link.send(msg_id, frame, retry)).await {
+            Ok(Ok(())) => {
                 trace!("Msg {msg_id:?} sent to {node_id:?}");
             }
-            Err(error) => {
+            Ok(Err(error)) => {
                 error!("Sending message (msg_id: {msg_id:?}) to {node_id:?} failed: {error}");
+                send_error(node_id, link_error(msg_id, error), comm_events.clone());
+            }
+            Err(_) => {
+                error!("Sending message (msg_id: {msg_id:?}) to {node_id:?} ran past its deadline");
                 send_error(node_id, Error::FailedSend(msg_id), comm_events.clone());
             }
         }
     });
 }
 
+/// Sends the msg kept in the retransmit buffer on new bidi-streams, until the node acks it
+/// or the deadline is reached.
 #[tracing::instrument(skip_all)]
-fn send_and_return_response<T: MsgTrait + 'static>(
+fn send_reliably<T: MsgTrait + 'static>(
     msg_id: MsgId,
     link: NodeLink,
-    bytes: Bytes,
+    deadline: Instant,
+    retry: RetryPolicy,
+    ack_timeout: Duration,
+    retransmits: RetransmitBuffer,
     comm_events: Sender<CommEvent<T>>,
 ) {
     let _handle = task::spawn(async move {
-        let bytes_len = bytes.len();
         let node_id = link.node();
-        trace!("Sending message bytes ({bytes_len} bytes) w/ {msg_id:?} to {node_id:?}");
+        if let Some(frame) = retransmits.frame(msg_id) {
+            if !wire::is_reliable(&frame) {
+                // the node would not ack it, since the signature doesn't ask it to
+                error!("Msg {msg_id:?} to {node_id:?} was not signed as a reliable msg");
+                retransmits.remove(msg_id);
+                send_error(node_id, Error::FailedSend(msg_id), comm_events);
+                return;
+            }
+        }
+        let acked = async {
+            let mut attempt = 0;
+            while let Some(frame) = retransmits.frame(msg_id) {
+                attempt += 1;
+                trace!("Sending reliable msg {msg_id:?} to {node_id:?}, attempt #{attempt}");
+                let retransmit_at = Instant::now() + ack_timeout;
+                let response = timeout_at(
+                    retransmit_at,
+                    link.send_with_bi_return_response(frame.clone(), msg_id, retry),
+                );
+                match response.await {
+                    Ok(Ok(response)) if wire::is_ack(&response, msg_id, node_id.id) => {
+                        trace!("Msg {msg_id:?} acked by {node_id:?}");
+                        retransmits.remove(msg_id);
+                        return;
+                    }
+                    Ok(Ok(_)) => {
+                        debug!(
+                            "Node {node_id:?} answered {msg_id:?} with something else than its ack"
+                        );
+                    }
+                    Ok(Err(error)) => {
+                        debug!(
+                            "Sending {msg_id:?} to {node_id:?} failed, to be sent again: {error}"
+                        );
+                    }
+                    Err(_) => {
+                        debug!("No ack from {node_id:?} for {msg_id:?} in time, sending it again");
+                    }
+                }
+                sleep_until(retransmit_at).await;
+            }
+        };
+        if timeout_at(deadline, acked).await.is_err() {
+            error!("Msg {msg_id:?} to {node_id:?} was not acked by its deadline");
+            retransmits.remove(msg_id);
+            send_error(node_id, Error::NotAcked(msg_id), comm_events);
+        }
+    });
+}
 
-        let node_response_bytes = match link.send_with_bi_return_response(bytes, msg_id).await {
-            Ok(response_bytes) => {
+/// Sends the request on a new bidi-stream, handing the response back to the requester.
+#[tracing::instrument(skip_all)]
+fn request(
+    msg_id: MsgId,
+    link: NodeLink,
+    frame: Frame,
+    deadline: Instant,
+    retry: RetryPolicy,
+    mut response: oneshot::Sender<Result<Frame>>,
+) {
+    let _handle = task::spawn(async move {
+        let node_id = link.node();
+        trace!(
+            "Sending request {msg_id:?} ({} bytes) to {node_id:?}",
+            frame.len()
+        );
+        let result = tokio::select! {
+            result = timeout_at(deadline, link.send_with_bi_return_response(frame, msg_id, retry)) => {
+                match result {
+                    Ok(result) => result.map_err(|error| {
+                        debug!("Request {msg_id:?} to {node_id:?} failed: {error}");
+                        link_error(msg_id, error)
+                    }),
+                    Err(_) => Err(Error::RequestTimeout(msg_id)),
+                }
+            }
+            _ = response.closed() => {
+                trace!("The requester stopped waiting for the response to {msg_id:?}");
+                return;
+            }
+        };
+        if response.send(result).is_err() {
+            trace!("The requester stopped waiting for the response to {msg_id:?}");
+        }
+    });
+}
+
+#[tracing::instrument(skip_all)]
+fn send_and_return_response<T: MsgTrait + 'static>(
+    msg_id: MsgId,
+    link: NodeLink,
+    frame: Frame,
+    deadline: Instant,
+    retry: RetryPolicy,
+    max_msg_size: u64,
+    comm_events: Sender<CommEvent<T>>,
+) {
+    let _handle = task::spawn(async move {
+        let frame_len = frame.len();
+        let node_id = link.node();
+        trace!("Sending message frame ({frame_len} bytes) w/ {msg_id:?} to {node_id:?}");
+
+        let response = timeout_at(
+            deadline,
+            link.send_with_bi_return_response(frame, msg_id, retry),
+        );
+        let node_response = match response.await {
+            Ok(Ok(response)) => {
                 debug!("Node response from {node_id:?} is in for {msg_id:?}");
-                response_bytes
+                response
             }
-            Err(error) => {
+            Ok(Err(error)) => {
                 error!("Sending message (msg_id: {msg_id:?}) to {node_id:?} failed: {error}");
-                send_error(node_id, Error::FailedSend(msg_id), comm_events.clone());
+                send_error(node_id, link_error(msg_id, error), comm_events.clone());
+                return;
+            }
+            Err(_) => {
+                error!("No response from {node_id:?} to {msg_id:?} by its deadline");
+                send_error(node_id, Error::RequestTimeout(msg_id), comm_events.clone());
                 return;
             }
         };
-        match NetworkMsg::from_bytes(node_response_bytes) {
+        match NetworkMsg::from_frame(node_response, max_msg_size) {
             Ok(wire_msg) => {
+                if let Err(error) = wire_msg.verify() {
+                    error!("Response to {msg_id:?} from {node_id:?} failed verification");
+                    send_error(node_id, error, comm_events.clone());
+                    return;
+                }
+                if let Err(error) = wire_msg.body {
+                    debug!("Node {node_id:?} responded to {msg_id:?} with an error: {error}");
+                    send_error(node_id, Error::ErrorResponse(*error), comm_events.clone());
+                    return;
+                }
                 listener::msg_received(wire_msg, node_id, None, comm_events.clone()).await;
             }
+            Err(error @ Error::MessageTooLarge { .. }) => {
+                error!("Response to {msg_id:?} from {node_id:?} is too large: {error}");
+                send_error(node_id, error, comm_events.clone());
+            }
             Err(error) => {
                 error!("Failed sending {msg_id:?} to {node_id:?}: {error:?}");
                 send_error(
@@ -388,76 +1126,150 @@ fn send_and_return_response<T: MsgTrait + 'static>(
 #[tracing::instrument(skip_all)]
 fn send_and_respond_on_stream<T: MsgTrait + 'static>(
     msg_id: MsgId,
-    node_bytes: BTreeMap<NetworkNode, (Option<NodeLink>, Bytes)>,
-    expected_targets: usize,
-    dst_stream: (NetworkNode, SendStream),
+    node_frames: BTreeMap<NetworkNode, (Option<NodeLink>, Frame)>,
+    settle: Settle,
+    max_msg_size: u64,
+    responder: Responder<QuorumResponse<T>>,
     comm_events: Sender<CommEvent<T>>,
 ) {
     let _handle = task::spawn(async move {
-        let (dst, stream) = dst_stream;
-
-        let tasks = node_bytes
+        let Settle {
+            quorum,
+            expected_targets,
+            deadline,
+            retry,
+            cancelled,
+        } = settle;
+        let mut pending: BTreeSet<_> = node_frames.keys().copied().collect();
+        let mut tasks: FuturesUnordered<_> = node_frames
             .into_iter()
-            .map(|pb| (pb, comm_events.clone()))
-            .map(|((node_id, (link, bytes)), comm_events)| async move {
+            .map(|(node_id, (link, frame))| async move {
                 let link = match link {
                     Some(link) => link,
                     None => return (node_id, Err(Error::ConnectingToUnknownNode(node_id))),
                 };
-
-                let node_response_bytes =
-                    match link.send_with_bi_return_response(bytes, msg_id).await {
-                        Ok(response_bytes) => response_bytes,
-                        Err(error) => {
-                            error!("Failed sending {msg_id:?} to {node_id:?}: {error:?}");
-                            send_error(node_id, Error::FailedSend(msg_id), comm_events);
-                            return (node_id, Err(Error::FailedSend(msg_id)));
-                        }
-                    };
-
+                let response = match link
+                    .send_with_bi_return_response(frame, msg_id, retry)
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(error) => return (node_id, Err(link_error(msg_id, error))),
+                };
                 debug!("Response from node {node_id:?} is in for {msg_id:?}");
-                (node_id, Ok(node_response_bytes))
-            });
-
-        let node_results: Vec<(NetworkNode, Result<Bytes>)> = join_all(tasks).await;
-
-        let succeeded: Vec<_> = node_results
-            .into_iter()
-            .filter_map(|(node_id, res)| match res {
-                Ok(bytes) => Some((node_id, bytes)),
-                Err(error) => {
-                    error!("Failed sending {msg_id:?} to {node_id:?}: {error:?}");
-                    send_error(node_id, Error::FailedSend(msg_id), comm_events.clone());
-                    None
-                }
+                (
+                    node_id,
+                    node_response(node_id, msg_id, response, max_msg_size),
+                )
             })
             .collect();
 
-        let some_failed = expected_targets > succeeded.len();
-        let all_ok_equal = || succeeded.windows(2).all(|w| are_equal(&w[0].1, &w[1].1));
-
-        let response_bytes = if some_failed || !all_ok_equal() {
-            match error_response::<T>(dst) {
-                None => {
-                    error!("Could not send the error response to client!");
+        // the handle being dropped without a word lets the fan-out run on
+        let cancelled = async move {
+            if cancelled.await.is_err() {
+                future::pending::<()>().await
+            }
+        };
+        tokio::pin!(cancelled);
+
+        // the payloads responded with, in the order they came in
+        let mut responses = vec![];
+        let mut unreachable = BTreeSet::new();
+        loop {
+            let (node_id, result) = tokio::select! {
+                biased;
+                _ = &mut cancelled => {
+                    debug!("Fan-out of {msg_id:?} cancelled, with {} nodes yet to respond", pending.len());
+                    if let Err(error) = responder.respond_error(Error::Cancelled(msg_id)).await {
+                        debug!("Could not tell the requester of {msg_id:?} it was cancelled: {error}");
+                    }
                     return;
                 }
-                Some(bytes) => bytes,
+                next = timeout_at(deadline, tasks.next()) => match next {
+                    Ok(Some(next)) => next,
+                    Ok(None) => break,
+                    Err(_) => {
+                        // the in-flight requests are dropped along with `tasks`
+                        for node_id in pending {
+                            error!("No response from {node_id:?} to {msg_id:?} by its deadline");
+                            let _ = unreachable.insert(node_id.id);
+                            send_error(node_id, Error::RequestTimeout(msg_id), comm_events.clone());
+                        }
+                        break;
+                    }
+                },
+            };
+            let _ = pending.remove(&node_id);
+            match result {
+                Ok(payload) => responses.push((node_id.id, payload)),
+                Err(error) => {
+                    error!("Failed sending {msg_id:?} to {node_id:?}: {error:?}");
+                    let _ = unreachable.insert(node_id.id);
+                    send_error(node_id, error, comm_events.clone());
+                }
             }
-        } else {
-            match succeeded.last() {
-                Some((_, bytes)) => bytes.clone(),
-                _ => {
-                    error!("Could not send the response to client!");
-                    return;
+            if quorum.is_settled_by_first() && !responses.is_empty() {
+                break;
+            }
+        }
+
+        let requester = responder.requester();
+        let result = match quorum.decide(expected_targets, &responses, &unreachable) {
+            Ok(response) => {
+                if !response.disagreed.is_empty() {
+                    warn!(
+                        "Nodes {:?} disagreed with the quorum on {msg_id:?}",
+                        response.disagreed
+                    );
                 }
+                responder.respond(response).await
             }
+            Err(error) => responder.respond_error(error).await,
         };
-
-        send_on_stream(msg_id, response_bytes, stream).await;
+        if let Err(error) = result {
+            error!("Could not send the response to {msg_id:?} to {requester:?} due to {error}!");
+        }
     });
 }
 
+/// How a fan-out is settled: by a quorum of the expected responses,
+/// retried as the policy tells, unless cut short at its deadline, or once cancelled.
+struct Settle {
+    quorum: Quorum,
+    expected_targets: usize,
+    deadline: Instant,
+    retry: RetryPolicy,
+    cancelled: oneshot::Receiver<()>,
+}
+
+/// The payload of a node's response to our msg, once verified to be its response.
+fn node_response<T: MsgTrait>(
+    node_id: NetworkNode,
+    msg_id: MsgId,
+    frame: Frame,
+    max_msg_size: u64,
+) -> Result<T> {
+    let response = NetworkMsg::<T>::from_frame(frame, max_msg_size)?;
+    response.verify()?;
+    if response.src != node_id.id || response.id != msg_id {
+        return Err(Error::InvalidMsgReceived(msg_id));
+    }
+    response.into_payload()
+}
+
+/// The error to report for a failed send over a link.
+fn link_error(msg_id: MsgId, error: NodeLinkError) -> Error {
+    match error {
+        NodeLinkError::IncompatibleVersions {
+            min_version,
+            max_version,
+        } => Error::IncompatiblePeer {
+            min_version,
+            max_version,
+        },
+        _ => Error::FailedSend(msg_id),
+    }
+}
+
 #[tracing::instrument(skip_all)]
 fn send_error<T: MsgTrait + 'static>(
     node_id: NetworkNode,
@@ -473,41 +1285,6 @@ fn send_error<T: MsgTrait + 'static>(
     });
 }
 
-#[tracing::instrument(skip_all)]
-async fn send_on_stream(msg_id: MsgId, bytes: Bytes, mut stream: SendStream) {
-    let placeholder = Bytes::from("placeholder");
-    match stream
-        .send_user_msg((placeholder.clone(), placeholder.clone(), bytes))
-        .await
-    {
-        Ok(()) => trace!("Response to {msg_id:?} sent to client."),
-        Err(error) => error!("Could not send the response to {msg_id:?} to client due to {error}!"),
-    }
-}
-
-fn error_response<T: MsgTrait>(dst: NetworkNode) -> Option<Bytes> {
-    let wire_msg = NetworkMsg::<T>::error_msg();
-    wire_msg.to_bytes().ok()
-}
-
-#[tracing::instrument(skip_all)]
-fn are_equal(a: &Bytes, b: &Bytes) -> bool {
-    are_bytes_equal(a.to_vec(), b.to_vec())
-}
-
-#[tracing::instrument(skip_all)]
-fn are_bytes_equal(one: Vec<u8>, other: Vec<u8>) -> bool {
-    if one.len() != other.len() {
-        return false;
-    }
-    for (a, b) in one.into_iter().zip(other) {
-        if a != b {
-            return false;
-        }
-    }
-    true
-}
-
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -6,26 +6,24 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{MsgId, NetworkNode, Result};
-
-use bytes::Bytes;
-use qp2p::{Connection, Endpoint};
+use super::{
+    retry::{Retries, RetryCounters, RetryPolicy},
+    transport::{Connection, Frame, Transport, TransportError},
+    wire::{self, Handshake},
+    MsgId, NetworkNode, NodeId, Result,
+};
 
 use custom_debug::Debug;
 use dashmap::DashMap;
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::time::{sleep, Duration};
+use tokio::time::{timeout, Duration};
 use tracing::{debug, error, instrument, trace, warn};
 
 type ConnId = String;
 
-/// These retries are how may _new_ connection attempts do we make.
-/// If we fail all of these, an error is raised, which in turn
-/// kicks off fault tracking for section nodes.
-const MAX_SENDJOB_RETRIES: usize = 3;
-
-const CONN_RETRY_WAIT: Duration = Duration::from_millis(100);
+/// How long a node has to answer our handshake on a new connection.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// A link to a node in our network.
 ///
@@ -36,19 +34,28 @@ const CONN_RETRY_WAIT: Duration = Duration::from_millis(100);
 /// The link shall be kept around as long as the node is deemed worth to keep contact with.
 #[derive(Clone)]
 pub(crate) struct NodeLink {
+    our_id: NodeId,
     node: NetworkNode,
-    endpoint: Endpoint,
+    transport: Arc<dyn Transport>,
     connections: NodeConnections,
+    retry_counters: Arc<RetryCounters>,
 }
 
-type NodeConnections = Arc<DashMap<ConnId, Arc<Connection>>>;
+type NodeConnections = Arc<DashMap<ConnId, Arc<dyn Connection>>>;
 
 impl NodeLink {
-    pub(crate) fn new(node: NetworkNode, endpoint: Endpoint) -> Self {
+    pub(crate) fn new(
+        our_id: NodeId,
+        node: NetworkNode,
+        transport: Arc<dyn Transport>,
+        retry_counters: Arc<RetryCounters>,
+    ) -> Self {
         Self {
+            our_id,
             node,
-            endpoint,
+            transport,
             connections: NodeConnections::default(),
+            retry_counters,
         }
     }
 
@@ -58,126 +65,83 @@ impl NodeLink {
 
     /// Sends out a UsrMsg on a bidi connection and awaits response bytes.
     /// As such this may be long running if response is returned slowly.
-    /// When sending a msg to a node, if it fails with an existing
-    /// cached connection, it will keep retrying till it either:
-    /// a. finds another cached connection which it succeeded with,
-    /// b. or it cleaned them all up from the cache creating a new connection
-    ///    to the node as last attempt.
+    /// When sending a msg to a node fails, the failing connection is cleaned up
+    /// and the msg retried as the policy allows, over another cached connection if any,
+    /// else over a new one. Once the msg is sent, it is not sent again for want of a response:
+    /// the node could end up handling it twice.
     pub(crate) async fn send_with_bi_return_response(
         &self,
-        bytes: Bytes,
+        frame: Frame,
         msg_id: MsgId,
-    ) -> Result<Bytes, NodeLinkError> {
+        retry: RetryPolicy,
+    ) -> Result<Frame, NodeLinkError> {
         let node = self.node;
         trace!(
             "Sending {msg_id:?} via a bi-stream to {node:?}, we have {} cached connections.",
             self.connections.len()
         );
-        let mut attempt = 0;
+        let mut retries = Retries::new(retry, self.retry_counters.clone());
         loop {
-            let conn = self
-                .connections
-                .iter()
-                .next()
-                .map(|entry| entry.value().clone());
-
-            let (conn, is_last_attempt) = if let Some(conn) = conn {
-                trace!(
-                    "Sending {msg_id:?} via bi-di-stream over existing connection {}, attempt #{attempt}.",
-                    conn.id()
-                );
-                (conn, false)
-            } else {
-                trace!("Sending {msg_id:?} via bi-di-stream over new connection to {node:?}, attempt #{attempt}.");
-                let conn =
-                    create_connection(node, &self.endpoint, self.connections.clone(), msg_id)
-                        .await?;
-                (conn, true)
-            };
-
-            attempt += 1;
-
-            let conn_id = conn.id();
-            trace!("Connection {conn_id} got to {node:?} for {msg_id:?}");
-            let (mut send_stream, recv_stream) = match conn.open_bi().await {
-                Ok(bi_stream) => bi_stream,
-                Err(err) => {
-                    error!("{msg_id:?} Error opening bi-stream over {conn_id}: {err:?}");
-                    // remove that broken conn
-                    let _conn = self.connections.remove(&conn_id);
-                    match is_last_attempt {
-                        true => {
-                            error!("Last attempt reached for {msg_id:?}, erroring out...");
-                            break Err(NodeLinkError::Connection(err));
-                        }
-                        false => {
-                            // tiny wait for comms/dashmap to cope with removal
-                            sleep(CONN_RETRY_WAIT).await;
-                            continue;
-                        }
+            let attempt = retries.attempt();
+            let conn = match self.get_or_connect(msg_id).await {
+                Ok(conn) => conn,
+                Err(error @ NodeLinkError::IncompatibleVersions { .. }) => {
+                    // no point in trying again
+                    error!("Cannot send {msg_id:?} to {node:?}: {error}");
+                    return Err(error);
+                }
+                Err(error) => {
+                    error!("Could not connect to {node:?} for {msg_id:?}, attempt #{attempt}: {error:?}");
+                    if !retries.backoff().await {
+                        error!("Last attempt reached for {msg_id:?}, erroring out...");
+                        return Err(error);
                     }
+                    continue;
                 }
             };
 
-            let stream_id = send_stream.id();
-            trace!("bidi {stream_id} opened for {msg_id:?} to {node:?}");
-            send_stream.set_priority(10);
-            let sn_bytes_format_wrap = (Default::default(), Default::default(), bytes.clone());
-            if let Err(err) = send_stream.send_user_msg(sn_bytes_format_wrap).await {
-                error!("Error sending bytes for {msg_id:?} over {stream_id}: {err:?}");
-                // remove that broken conn
-                let _conn = self.connections.remove(&conn_id);
-                match is_last_attempt {
-                    true => break Err(NodeLinkError::Send(err)),
-                    false => {
-                        // tiny wait for comms/dashmap to cope with removal
-                        sleep(CONN_RETRY_WAIT).await;
-                        continue;
-                    }
+            let conn_id = conn.id();
+            trace!("Sending {msg_id:?} via bi-di-stream over connection {conn_id} to {node:?}, attempt #{attempt}.");
+            match conn.request(frame.clone()).await {
+                Ok(response) => {
+                    retries.succeeded();
+                    return Ok(response);
+                }
+                Err(err @ TransportError::Recv(_)) => {
+                    // the node may or may not have handled it, so it counts as neither
+                    // recovered nor given up on
+                    error!("No response to {msg_id:?} from {node:?} over {conn_id}: {err:?}");
+                    return Err(NodeLinkError::Transport(err));
                 }
-            }
-
-            trace!("{msg_id:?} sent on {stream_id} to {node:?}");
-
-            // unblock + move finish off thread as it's not strictly related to the sending of the msg.
-            let stream_id_clone = stream_id.clone();
-            let _handle = tokio::spawn(async move {
-                // Attempt to gracefully terminate the stream.
-                // If this errors it does _not_ mean our message has not been sent
-                let result = send_stream.finish().await;
-                trace!("{msg_id:?} finished {stream_id_clone} to {node:?}: {result:?}");
-            });
-
-            match recv_stream.read().await {
-                Ok((_sn, _bytes, response)) => break Ok(response),
                 Err(err) => {
-                    error!("Error receiving response to {msg_id:?} from {node:?} over {stream_id}: {err:?}");
+                    error!("Error on bi-stream for {msg_id:?} to {node:?} over {conn_id}: {err:?}");
+                    // remove that broken conn
                     let _conn = self.connections.remove(&conn_id);
-                    if is_last_attempt {
-                        break Err(NodeLinkError::Recv(err));
+                    if !retries.backoff().await {
+                        error!("Last attempt reached for {msg_id:?}, erroring out...");
+                        return Err(NodeLinkError::Transport(err));
                     }
-
-                    // tiny wait for comms/dashmap to cope with removal
-                    sleep(CONN_RETRY_WAIT).await;
                 }
             }
         }
     }
 
-    #[instrument(skip(self, bytes))]
-    pub(crate) async fn send(&mut self, msg_id: MsgId, bytes: Bytes) -> Result<(), NodeLinkError> {
-        let mut connection_retries = 0;
-
+    /// Sends the msg over a cached connection, or a new one, retrying as the policy allows.
+    /// Once out of attempts, an error is raised, which in turn
+    /// kicks off fault tracking for section nodes.
+    #[instrument(skip(self, frame))]
+    pub(crate) async fn send(
+        &self,
+        msg_id: MsgId,
+        frame: Frame,
+        retry: RetryPolicy,
+    ) -> Result<(), NodeLinkError> {
         let node = self.node;
+        let mut retries = Retries::new(retry, self.retry_counters.clone());
 
         loop {
-            trace!("Sending to {node:?} over connection: {msg_id:?}");
-
-            if connection_retries > MAX_SENDJOB_RETRIES {
-                let error_to_report = NodeLinkError::MaxRetriesReached(MAX_SENDJOB_RETRIES);
-                debug!("{error_to_report}: {msg_id:?}");
-                return Err(error_to_report);
-            }
+            let attempt = retries.attempt();
+            trace!("Sending to {node:?} over connection: {msg_id:?}, attempt #{attempt}");
 
             // Keep this connection creation/retrieval as blocking.
             // This avoids us making many many connection attempts to the same node.
@@ -185,21 +149,20 @@ impl NodeLink {
             // If a valid connection exists, retrieval is fast.
             //
             // Attempt to get a connection or make one to another node.
-            // if there's no successful connection, we requeue the job after a wait
+            // if there's no successful connection, we retry after a backoff
             // incase there's been a delay adding the connection to Comms
             let conn = match self.get_or_connect(msg_id).await {
                 Ok(conn) => conn,
+                Err(error @ NodeLinkError::IncompatibleVersions { .. }) => {
+                    // no point in trying again
+                    error!("Cannot send {msg_id:?} to {node:?}: {error}");
+                    return Err(error);
+                }
                 Err(error) => {
-                    error!("Error when attempting to send {msg_id:?} to node. Job will be reenqueued for another attempt after a small timeout: {error:?}");
-
-                    // only increment connection attempts if our connections set is empty
-                    // and so we'll be trying to create a fresh connection
-                    if self.connections.is_empty() {
-                        connection_retries += 1;
+                    error!("Error when attempting to send {msg_id:?} to node. Job will be retried after a backoff: {error:?}");
+                    if !retries.backoff().await {
+                        return Err(Self::out_of_attempts(msg_id, attempt));
                     }
-
-                    // we await here in case the connection is fresh and has not yet been added
-                    sleep(CONN_RETRY_WAIT).await;
                     continue;
                 }
             };
@@ -208,23 +171,17 @@ impl NodeLink {
             debug!("Connection got for sendjob: {msg_id:?}, with conn_id: {conn_id:?}");
 
             let send_resp =
-                Self::send_with_connection(conn, bytes.clone(), self.connections.clone()).await;
+                Self::send_with_connection(conn, frame.clone(), self.connections.clone()).await;
 
             match send_resp {
                 Ok(()) => {
+                    retries.succeeded();
                     return Ok(());
                 }
                 Err(err) => {
                     if err.is_local_close() {
                         let conns_count = self.connections.len();
                         error!("Node connection dropped when trying to send {msg_id:?} (we still have {conns_count:?} connections): {err:?}");
-                        // we can retry if we've more connections!
-                        if conns_count <= 1 {
-                            debug!(
-                                "No connections left on this session to {node:?}, terminating session.",
-                            );
-                            connection_retries += 1;
-                        }
                     }
 
                     warn!(
@@ -232,14 +189,22 @@ impl NodeLink {
                     );
 
                     // we await here in case the connection is fresh and has not yet been added
-                    sleep(CONN_RETRY_WAIT).await;
+                    if !retries.backoff().await {
+                        return Err(Self::out_of_attempts(msg_id, attempt));
+                    }
                 }
             }
         }
     }
 
+    fn out_of_attempts(msg_id: MsgId, attempts: usize) -> NodeLinkError {
+        let error_to_report = NodeLinkError::MaxRetriesReached(attempts);
+        debug!("{error_to_report}: {msg_id:?}");
+        error_to_report
+    }
+
     // Gets an existing connection or creates a new one
-    async fn get_or_connect(&mut self, msg_id: MsgId) -> Result<Arc<Connection>, NodeLinkError> {
+    async fn get_or_connect(&self, msg_id: MsgId) -> Result<Arc<dyn Connection>, NodeLinkError> {
         let node = self.node;
         trace!("{msg_id:?} Grabbing a connection to {node:?} from cached set.");
 
@@ -253,23 +218,29 @@ impl NodeLink {
             Ok(conn)
         } else {
             trace!("{msg_id:?} No connection found to {node:?}, creating a new one.");
-            create_connection(node, &self.endpoint, self.connections.clone(), msg_id).await
+            create_connection(
+                self.our_id,
+                node,
+                &*self.transport,
+                self.connections.clone(),
+                msg_id,
+            )
+            .await
         }
     }
 
     /// Send a message to the node using the given connection.
     #[instrument(skip_all)]
     async fn send_with_connection(
-        conn: Arc<Connection>,
-        bytes: Bytes,
+        conn: Arc<dyn Connection>,
+        frame: Frame,
         connections: NodeConnections,
     ) -> Result<(), NodeLinkError> {
         let conn_id = conn.id();
         let conns_count = connections.len();
         trace!("We have {conns_count} open connections to node {conn_id}.");
 
-        let sn_bytes_format_wrap = (Default::default(), Default::default(), bytes);
-        conn.send_with(sn_bytes_format_wrap, 0 /* priority */).await.map_err(|error| {
+        conn.send(frame).await.map_err(|error| {
             error!(
                 "Error sending out msg... We have {conns_count} open connections to node {conn_id}: {error:?}",
             );
@@ -281,60 +252,83 @@ impl NodeLink {
             debug!("Connection removed from session: {conn_id}");
             // dont close just let the conn timeout incase msgs are coming in...
             // it's removed from our node tracking, so won't be used again for sending.
-            NodeLinkError::Send(error)
+            NodeLinkError::Transport(error)
         })
     }
 }
 
 async fn create_connection(
+    our_id: NodeId,
     node: NetworkNode,
-    endpoint: &Endpoint,
+    transport: &dyn Transport,
     connections: NodeConnections,
     msg_id: MsgId,
-) -> Result<Arc<Connection>, NodeLinkError> {
+) -> Result<Arc<dyn Connection>, NodeLinkError> {
     debug!("{msg_id:?} create conn attempt to {node:?}");
-    let (conn, _) = endpoint
-        .connect_to(&node.addr)
-        .await
-        .map_err(NodeLinkError::Connection)?;
+    let conn = transport.connect(node.addr).await?;
 
     trace!(
         "{msg_id:?}: ConnectionOpened to {} (id: {})",
-        conn.remote_address(),
+        conn.remote_addr(),
         conn.id()
     );
 
+    let version = match timeout(HANDSHAKE_TIMEOUT, handshake(our_id, node, &*conn)).await {
+        Ok(result) => result?,
+        Err(_) => return Err(NodeLinkError::HandshakeTimeout),
+    };
+
     let conn_id = conn.id();
     debug!("Inserting connection into node link: {conn_id}");
 
-    let conn = Arc::new(conn);
     let _ = connections.insert(conn_id.clone(), conn.clone());
-    debug!("Connection INSERTED into node link: {conn_id}");
+    debug!("Connection INSERTED into node link: {conn_id}, speaking version {version}");
 
     Ok(conn)
 }
 
+/// Agrees with the node on the protocol version to speak over the connection.
+async fn handshake(
+    our_id: NodeId,
+    node: NetworkNode,
+    conn: &dyn Connection,
+) -> Result<u16, NodeLinkError> {
+    let hello = Handshake::hello()
+        .to_frame(our_id, Some(node.id))
+        .map_err(|_| NodeLinkError::InvalidHandshake)?;
+    let response = conn.request(hello).await?;
+    match Handshake::from_frame(&response) {
+        Ok(Handshake::Accept { version }) if wire::supported_versions().contains(&version) => {
+            Ok(version)
+        }
+        Ok(Handshake::Refuse {
+            min_version,
+            max_version,
+        }) => Err(NodeLinkError::IncompatibleVersions {
+            min_version,
+            max_version,
+        }),
+        _ => Err(NodeLinkError::InvalidHandshake),
+    }
+}
+
 /// Errors that can be returned from `Comm::send_to_one`.
 #[derive(Debug, Error)]
 pub enum NodeLinkError {
-    #[error("Failed to connect: {0:?}")]
-    Connection(qp2p::ConnectionError),
-    #[error("Failed to send a message: {0:?}")]
-    Send(qp2p::SendError),
-    #[error("Failed to receive a message: {0:?}")]
-    Recv(qp2p::RecvError),
+    #[error(transparent)]
+    Transport(#[from] TransportError),
     #[error("Max number of attempts ({0}) to send msg to the node has been reached")]
     MaxRetriesReached(usize),
+    #[error("Node only speaks protocol versions {min_version} to {max_version}")]
+    IncompatibleVersions { min_version: u16, max_version: u16 },
+    #[error("Node answered our handshake with something else than a version we speak")]
+    InvalidHandshake,
+    #[error("Node did not answer our handshake in time")]
+    HandshakeTimeout,
 }
 
 impl NodeLinkError {
     fn is_local_close(&self) -> bool {
-        matches!(
-            self,
-            NodeLinkError::Connection(qp2p::ConnectionError::Closed(qp2p::Close::Local))
-                | NodeLinkError::Send(qp2p::SendError::ConnectionLost(
-                    qp2p::ConnectionError::Closed(qp2p::Close::Local)
-                ))
-        )
+        matches!(self, NodeLinkError::Transport(TransportError::LocalClose))
     }
 }
@@ -0,0 +1,52 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The msgs still being sent, so that they can be waited for before closing the endpoint.
+#[derive(Clone, Debug)]
+pub(crate) struct PendingSends {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Default for PendingSends {
+    fn default() -> Self {
+        Self {
+            count: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+impl PendingSends {
+    /// Counts in a msg, until the returned guard is dropped.
+    pub(crate) fn start(&self) -> PendingSend {
+        self.count.send_modify(|count| *count += 1);
+        PendingSend {
+            count: self.count.clone(),
+        }
+    }
+
+    /// Waits for all msgs counted in to be done with.
+    pub(crate) async fn flushed(&self) {
+        // we hold the sender, so the receiver can't see it closed
+        let _ = self.count.subscribe().wait_for(|count| *count == 0).await;
+    }
+}
+
+/// A msg being sent, counted out when dropped, once sent or given up on.
+#[derive(Debug)]
+pub(crate) struct PendingSend {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for PendingSend {
+    fn drop(&mut self) {
+        self.count.send_modify(|count| *count -= 1);
+    }
+}
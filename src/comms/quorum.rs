@@ -0,0 +1,119 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{ErrorCode, ErrorResponse, MsgTrait, NodeId};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// How the responses of the nodes a msg was fanned out to are settled into a single one.
+///
+/// Responses are compared by their payloads, whoever signed them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quorum {
+    /// Every node responded, all with the same payload.
+    #[default]
+    AllEqual,
+    /// More than half of the nodes responded with the same payload.
+    Majority,
+    /// At least this many nodes responded with the same payload.
+    Threshold(usize),
+    /// The first node to respond, without waiting for the others.
+    FirstSuccess,
+}
+
+impl Quorum {
+    /// Whether the first response settles it, so that the others need not be waited for.
+    pub(crate) fn is_settled_by_first(&self) -> bool {
+        matches!(self, Self::FirstSuccess)
+    }
+
+    /// The payload agreed on by the responses, in the order they came in,
+    /// out of the `expected` responses asked for, the `unreachable` nodes having failed to respond.
+    pub(crate) fn decide<T: MsgTrait>(
+        &self,
+        expected: usize,
+        responses: &[(NodeId, T)],
+        unreachable: &BTreeSet<NodeId>,
+    ) -> Result<QuorumResponse<T>, ErrorResponse> {
+        // the payloads, with the nodes which responded with each
+        let mut tally: Vec<(&T, BTreeSet<NodeId>)> = vec![];
+        for (node, payload) in responses {
+            match tally.iter_mut().find(|(other, _)| *other == payload) {
+                Some((_, nodes)) => {
+                    let _ = nodes.insert(*node);
+                }
+                None => tally.push((payload, BTreeSet::from([*node]))),
+            }
+        }
+
+        let winner = match self {
+            Self::FirstSuccess => tally.first(),
+            // ties go to the payload which came in first
+            _ => tally.iter().rev().max_by_key(|(_, nodes)| nodes.len()),
+        };
+        let (value, agreed) = match winner {
+            Some(winner) => winner,
+            None => {
+                return Err(
+                    ErrorResponse::new(ErrorCode::NodeUnreachable, "No node responded")
+                        .with_failed_nodes(unreachable.iter().copied()),
+                )
+            }
+        };
+        let settled = match self {
+            Self::AllEqual => tally.len() == 1 && agreed.len() == expected,
+            Self::Majority => 2 * agreed.len() > expected,
+            Self::Threshold(threshold) => agreed.len() >= *threshold,
+            Self::FirstSuccess => true,
+        };
+        let disagreed: BTreeSet<_> = responses
+            .iter()
+            .map(|(node, _)| *node)
+            .filter(|node| !agreed.contains(node))
+            .collect();
+        if settled {
+            return Ok(QuorumResponse {
+                value: (*value).clone(),
+                disagreed,
+            });
+        }
+
+        // had the unreachable nodes responded alike, there may have been a quorum
+        let error = if disagreed.is_empty() {
+            ErrorResponse::new(
+                ErrorCode::NodeUnreachable,
+                format!(
+                    "{} of {expected} nodes agreed, others could not be reached",
+                    agreed.len()
+                ),
+            )
+        } else {
+            ErrorResponse::new(
+                ErrorCode::QuorumDisagreed,
+                format!(
+                    "{} of {expected} nodes agreed, {} disagreed",
+                    agreed.len(),
+                    disagreed.len()
+                ),
+            )
+        };
+        Err(error.with_failed_nodes(disagreed.into_iter().chain(unreachable.iter().copied())))
+    }
+}
+
+/// The response agreed on by the nodes a msg was fanned out to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuorumResponse<T> {
+    /// The payload the quorum agreed on.
+    pub value: T,
+    /// The nodes which responded with another payload.
+    pub disagreed: BTreeSet<NodeId>,
+}
+
+impl<T: MsgTrait> MsgTrait for QuorumResponse<T> {}
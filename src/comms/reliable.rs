@@ -0,0 +1,96 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! At-least-once delivery: the sender keeps each reliable msg until its recipient acks it,
+//! sending it again as long as it doesn't, and the recipient hands each msg on only once.
+
+use super::{pending::PendingSend, transport::Frame, MsgId, NetworkNode, NodeId};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    collections::{BTreeSet, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// How many of the reliable msgs last received we remember, to drop copies of them.
+const SEEN_CAPACITY: usize = 10_000;
+
+/// The reliable msgs we sent, kept until acked so that they can be sent again.
+///
+/// Each holds on to its `PendingSend`, so that a flush waits for it to be acked or given up on.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RetransmitBuffer {
+    msgs: Arc<DashMap<MsgId, (NetworkNode, Frame, PendingSend)>>,
+}
+
+impl RetransmitBuffer {
+    /// Keeps the msg until acked, unless it already is kept, returning whether it was new.
+    pub(crate) fn insert(
+        &self,
+        msg_id: MsgId,
+        node_id: NetworkNode,
+        frame: Frame,
+        pending: PendingSend,
+    ) -> bool {
+        match self.msgs.entry(msg_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                let _ = entry.insert((node_id, frame, pending));
+                true
+            }
+        }
+    }
+
+    /// The frame to send the msg in, if it is still unacked.
+    pub(crate) fn frame(&self, msg_id: MsgId) -> Option<Frame> {
+        self.msgs.get(&msg_id).map(|entry| entry.value().1.clone())
+    }
+
+    /// Drops the msg, once acked or given up on.
+    pub(crate) fn remove(&self, msg_id: MsgId) {
+        let _ = self.msgs.remove(&msg_id);
+    }
+
+    pub(crate) fn ids(&self) -> BTreeSet<MsgId> {
+        self.msgs.iter().map(|entry| *entry.key()).collect()
+    }
+}
+
+/// The reliable msgs last received, by sender and id, shared by all connections.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SeenMsgs {
+    seen: Arc<Mutex<Seen>>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    msgs: HashSet<(NodeId, MsgId)>,
+    /// The msgs in the order they were first seen, to forget the oldest ones first.
+    order: VecDeque<(NodeId, MsgId)>,
+}
+
+impl SeenMsgs {
+    /// Remembers the msg, returning whether it was seen for the first time.
+    pub(crate) fn insert(&self, src: NodeId, msg_id: MsgId) -> bool {
+        // the lock is never held across a panic, so it can't be poisoned
+        let mut seen = self
+            .seen
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !seen.msgs.insert((src, msg_id)) {
+            return false;
+        }
+        seen.order.push_back((src, msg_id));
+        if seen.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = seen.order.pop_front() {
+                let _ = seen.msgs.remove(&oldest);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{
+    transport::{Frame, SendStream},
+    ErrorResponse, Keypair, MsgId, MsgTrait, NetworkMsg, NetworkNode, Result,
+};
+
+use std::{marker::PhantomData, sync::Arc};
+use tracing::{trace, warn};
+
+/// Responds to a msg which came on a bidi-stream.
+///
+/// The response carries the id of the msg, is signed by us and meant for its sender.
+/// Responding consumes the responder, finishing the stream, so that each msg gets a single response.
+/// A responder dropped without responding finishes the stream with no response.
+#[derive(Debug)]
+pub struct Responder<T> {
+    request: PendingRequest,
+    _payload: PhantomData<fn(T)>,
+}
+
+impl<T: MsgTrait> Responder<T> {
+    pub(crate) fn new(
+        keypair: Arc<Keypair>,
+        msg_id: MsgId,
+        requester: NetworkNode,
+        stream: SendStream,
+    ) -> Self {
+        Self::from_request(PendingRequest {
+            keypair,
+            msg_id,
+            requester,
+            stream,
+        })
+    }
+
+    pub(crate) fn from_request(request: PendingRequest) -> Self {
+        Self {
+            request,
+            _payload: PhantomData,
+        }
+    }
+
+    pub(crate) fn into_request(self) -> PendingRequest {
+        self.request
+    }
+
+    /// The id of the msg responded to.
+    pub fn msg_id(&self) -> MsgId {
+        self.request.msg_id
+    }
+
+    /// The node which sent the msg, and awaits the response.
+    pub fn requester(&self) -> NetworkNode {
+        self.request.requester
+    }
+
+    /// Sends the payload as the response.
+    pub async fn respond(self, payload: T) -> Result<()> {
+        let PendingRequest {
+            keypair,
+            msg_id,
+            requester,
+            ..
+        } = &self.request;
+        let frame = NetworkMsg::signed(keypair, *msg_id, requester.id, payload)?.to_frame()?;
+        self.send(frame).await
+    }
+
+    /// Responds that the msg could not be handled, and why.
+    pub async fn respond_error(self, error: impl Into<ErrorResponse>) -> Result<()> {
+        let PendingRequest {
+            keypair,
+            msg_id,
+            requester,
+            ..
+        } = &self.request;
+        let error = error.into();
+        warn!("Responding to {msg_id:?} from {requester:?} with an error: {error}");
+        let frame =
+            NetworkMsg::<T>::signed_error(keypair, *msg_id, requester.id, error)?.to_frame()?;
+        self.send(frame).await
+    }
+
+    async fn send(self, frame: Frame) -> Result<()> {
+        let PendingRequest {
+            msg_id,
+            requester,
+            stream,
+            ..
+        } = self.request;
+        stream.send(frame).await?;
+        trace!("Response to {msg_id:?} sent to {requester:?}");
+        Ok(())
+    }
+}
+
+/// A msg received on a bidi-stream, awaiting its response, whatever its payload type.
+#[derive(custom_debug::Debug)]
+pub(crate) struct PendingRequest {
+    #[debug(skip)]
+    keypair: Arc<Keypair>,
+    msg_id: MsgId,
+    requester: NetworkNode,
+    stream: SendStream,
+}
@@ -0,0 +1,167 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use rand::Rng;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::time::{sleep, Duration, Instant};
+use tracing::trace;
+
+/// Attempts we make at sending a msg by default, the first one included.
+const DEFAULT_MAX_ATTEMPTS: usize = 4;
+
+/// How long we wait before the first retry by default.
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest we wait between two attempts by default.
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// How a failed send is retried.
+///
+/// The wait before each retry doubles, up to `max_backoff`, and unless told otherwise is jittered
+/// to somewhere between half of it and all of it, so that nodes failing together
+/// don't all retry together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts made at most, the first one included.
+    pub max_attempts: usize,
+    /// How long to wait before the first retry.
+    pub initial_backoff: Duration,
+    /// The longest to wait between two attempts.
+    pub max_backoff: Duration,
+    /// How long after the first attempt we may still retry, if at all limited.
+    pub max_elapsed: Option<Duration>,
+    /// Whether the waits are jittered.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_elapsed: None,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A single attempt, never retried.
+    pub fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The wait before the retry following the passed in number of attempts, before jitter.
+    fn backoff(&self, attempts: usize) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(31) as u32;
+        self.initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff)
+    }
+}
+
+/// How many attempts at sending msgs were made and retried, since the `Comm` was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// Attempts at sending msgs, first ones and retries alike.
+    pub attempts: u64,
+    /// Attempts made after a failed one.
+    pub retries: u64,
+    /// Msgs which were sent after having been retried.
+    pub recovered: u64,
+    /// Msgs given up on once their retry policy ran out.
+    pub exhausted: u64,
+}
+
+/// The counters behind the `RetryStats`, shared by the links of a `Comm`.
+#[derive(Debug, Default)]
+pub(crate) struct RetryCounters {
+    attempts: AtomicU64,
+    retries: AtomicU64,
+    recovered: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+impl RetryCounters {
+    pub(crate) fn stats(&self) -> RetryStats {
+        RetryStats {
+            attempts: self.attempts.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The attempts made at sending a single msg, under its retry policy.
+pub(crate) struct Retries {
+    policy: RetryPolicy,
+    counters: Arc<RetryCounters>,
+    attempts: usize,
+    started: Instant,
+}
+
+impl Retries {
+    pub(crate) fn new(policy: RetryPolicy, counters: Arc<RetryCounters>) -> Self {
+        Self {
+            policy,
+            counters,
+            attempts: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Counts in an attempt, returning how many were made so far.
+    pub(crate) fn attempt(&mut self) -> usize {
+        self.attempts += 1;
+        let _ = self.counters.attempts.fetch_add(1, Ordering::Relaxed);
+        if self.attempts > 1 {
+            let _ = self.counters.retries.fetch_add(1, Ordering::Relaxed);
+        }
+        self.attempts
+    }
+
+    /// Counts in the msg having been sent.
+    pub(crate) fn succeeded(&self) {
+        if self.attempts > 1 {
+            let _ = self.counters.recovered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Waits for the backoff before the next attempt, or returns false if the policy
+    /// allows for no further attempt, in which case the msg is counted as given up on.
+    pub(crate) async fn backoff(&self) -> bool {
+        let backoff = self.policy.backoff(self.attempts);
+        let backoff = if self.policy.jitter {
+            rand::thread_rng().gen_range(backoff / 2..=backoff)
+        } else {
+            backoff
+        };
+        let out_of_time = self
+            .policy
+            .max_elapsed
+            .is_some_and(|max| self.started.elapsed() + backoff > max);
+        if self.attempts >= self.policy.max_attempts || out_of_time {
+            let _ = self.counters.exhausted.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        trace!(
+            "Retrying after {backoff:?}, {} attempts made",
+            self.attempts
+        );
+        sleep(backoff).await;
+        true
+    }
+}
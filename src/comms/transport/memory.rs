@@ -0,0 +1,454 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{
+    Connection, Frame, IncomingConnection, IncomingConnections, IncomingMsg, ResponseStream,
+    SendStream, Transport, TransportError,
+};
+
+use async_trait::async_trait;
+use std::{
+    collections::BTreeMap,
+    fmt, future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{sleep, Duration},
+};
+use tracing::trace;
+
+/// Number of connections and msgs per connection buffered until the listener picks them up.
+const INCOMING_CHANNEL_SIZE: usize = 1_000;
+
+/// Hooks deciding how a `MemoryNetwork` delivers the frames sent over it,
+/// to have it delay, lose or duplicate them as a real network would.
+pub trait NetworkFaults: Send + Sync + fmt::Debug {
+    /// The delays after which the copies of a frame sent from `from` to `to` are delivered:
+    /// none for the frame to be lost, more than one for it to be duplicated.
+    fn deliveries(&self, from: SocketAddr, to: SocketAddr, frame: &Frame) -> Vec<Duration>;
+
+    /// Whether a copy of a frame from `from` still reaches `to` once its delay is up.
+    fn can_deliver(&self, _from: SocketAddr, _to: SocketAddr) -> bool {
+        true
+    }
+}
+
+/// An in-process network, which transports bind to instead of sockets.
+///
+/// Frames are delivered at once and in order, unless the network was given faults.
+/// Cloning it gives a handle to the same network.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<BTreeMap<SocketAddr, Listener>>>,
+    next_conn_id: Arc<AtomicU64>,
+    faults: Option<Arc<dyn NetworkFaults>>,
+}
+
+#[derive(Debug)]
+struct Listener {
+    connections: mpsc::Sender<IncomingConnection>,
+    closed: Arc<AtomicBool>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A network delivering the frames sent over it as the faults decide.
+    pub fn with_faults(faults: Arc<dyn NetworkFaults>) -> Self {
+        Self {
+            faults: Some(faults),
+            ..Self::default()
+        }
+    }
+
+    /// Binds a transport to the passed in address, which must not be in use on this network.
+    pub fn bind(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<(MemoryTransport, IncomingConnections), TransportError> {
+        let mut listeners = self.lock();
+        if listeners.contains_key(&addr) {
+            return Err(TransportError::AddrInUse(addr));
+        }
+        let (connections, receiver) = mpsc::channel(INCOMING_CHANNEL_SIZE);
+        let closed = Arc::new(AtomicBool::new(false));
+        let _ = listeners.insert(
+            addr,
+            Listener {
+                connections,
+                closed: closed.clone(),
+            },
+        );
+
+        let transport = MemoryTransport {
+            network: self.clone(),
+            addr,
+            closed,
+        };
+        Ok((transport, receiver))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<SocketAddr, Listener>> {
+        lock(&self.listeners)
+    }
+}
+
+/// Transport over a `MemoryNetwork`.
+#[derive(Debug, Clone)]
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    closed: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<Arc<dyn Connection>, TransportError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(TransportError::LocalClose);
+        }
+        let (connections, remote_closed) = match self.network.lock().get(&addr) {
+            Some(listener) => (listener.connections.clone(), listener.closed.clone()),
+            None => {
+                return Err(TransportError::Connection(format!(
+                    "nothing listens on {addr}"
+                )))
+            }
+        };
+
+        let id = self.network.next_conn_id.fetch_add(1, Ordering::SeqCst);
+        let id = format!("mem-{id}");
+        let (msgs_sender, msgs) = mpsc::channel(INCOMING_CHANNEL_SIZE);
+        let incoming = IncomingConnection {
+            id: id.clone(),
+            remote_addr: self.addr,
+            msgs,
+        };
+        connections
+            .send(incoming)
+            .await
+            .map_err(|_| TransportError::Connection(format!("{addr} stopped listening")))?;
+        trace!("Connection {id} opened from {} to {addr}", self.addr);
+
+        Ok(Arc::new(MemoryConnection {
+            id,
+            local_addr: self.addr,
+            remote_addr: addr,
+            msgs: msgs_sender,
+            closed: self.closed.clone(),
+            remote_closed,
+            faults: self.network.faults.clone(),
+        }))
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let mut listeners = self.network.lock();
+        // the address may have been bound again since we closed
+        if listeners
+            .get(&self.addr)
+            .is_some_and(|listener| Arc::ptr_eq(&listener.closed, &self.closed))
+        {
+            let _ = listeners.remove(&self.addr);
+        }
+    }
+}
+
+struct MemoryConnection {
+    id: String,
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
+    msgs: mpsc::Sender<Result<IncomingMsg, TransportError>>,
+    closed: Arc<AtomicBool>,
+    remote_closed: Arc<AtomicBool>,
+    faults: Option<Arc<dyn NetworkFaults>>,
+}
+
+impl MemoryConnection {
+    /// Delivers the frame, along with a stream to respond on if any,
+    /// returning the number of copies of it which are on their way.
+    async fn deliver(
+        &self,
+        frame: Frame,
+        send_stream: impl Fn() -> Option<SendStream>,
+    ) -> Result<usize, TransportError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(TransportError::LocalClose);
+        }
+        if self.remote_closed.load(Ordering::SeqCst) {
+            return Err(TransportError::Send(format!(
+                "{} closed the connection",
+                self.remote_addr
+            )));
+        }
+        let faults = match &self.faults {
+            Some(faults) => faults,
+            None => {
+                let msg = IncomingMsg {
+                    frame,
+                    send_stream: send_stream(),
+                };
+                self.msgs.send(Ok(msg)).await.map_err(|_| {
+                    TransportError::Send(format!("{} dropped the connection", self.remote_addr))
+                })?;
+                return Ok(1);
+            }
+        };
+
+        let (from, to) = (self.local_addr, self.remote_addr);
+        let delays = faults.deliveries(from, to, &frame);
+        for delay in &delays {
+            let msg = IncomingMsg {
+                frame: frame.clone(),
+                send_stream: send_stream(),
+            };
+            let (delay, faults, msgs) = (*delay, faults.clone(), self.msgs.clone());
+            let remote_closed = self.remote_closed.clone();
+            let _handle = tokio::spawn(async move {
+                sleep(delay).await;
+                if !remote_closed.load(Ordering::SeqCst) && faults.can_deliver(from, to) {
+                    let _ = msgs.send(Ok(msg)).await;
+                }
+            });
+        }
+        Ok(delays.len())
+    }
+}
+
+#[async_trait]
+impl Connection for MemoryConnection {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    async fn send(&self, frame: Frame) -> Result<(), TransportError> {
+        let _copies = self.deliver(frame, || None).await?;
+        Ok(())
+    }
+
+    async fn request(&self, frame: Frame) -> Result<Frame, TransportError> {
+        let (sender, receiver) = oneshot::channel();
+        // only the first of the copies of the request to respond gets to
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        let stream = || {
+            Some(SendStream::new(MemoryResponseStream {
+                id: format!("{}-bi", self.id),
+                sender: sender.clone(),
+            }))
+        };
+        let copies = self.deliver(frame, stream).await?;
+        if copies == 0 {
+            // nobody is to respond to a lost request, so no response ever comes
+            return future::pending().await;
+        }
+        drop(sender);
+        receiver
+            .await
+            .map_err(|_| TransportError::Recv("the stream was dropped without a response".into()))
+    }
+}
+
+struct MemoryResponseStream {
+    id: String,
+    sender: Arc<Mutex<Option<oneshot::Sender<Frame>>>>,
+}
+
+#[async_trait]
+impl ResponseStream for MemoryResponseStream {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    async fn send(self: Box<Self>, frame: Frame) -> Result<(), TransportError> {
+        let sender = lock(&self.sender).take();
+        match sender {
+            Some(sender) => sender
+                .send(frame)
+                .map_err(|_| TransportError::Send("the requester stopped waiting".into())),
+            // another copy of the request was responded to already
+            None => Ok(()),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // the locks are never held across a panic, so they can't be poisoned
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn addr(host: u8) -> SocketAddr {
+        ([10, 0, 0, host], 12000).into()
+    }
+
+    fn frame(payload: &'static [u8]) -> Frame {
+        Frame {
+            header: Bytes::new(),
+            dst: Bytes::new(),
+            payload: Bytes::from_static(payload),
+        }
+    }
+
+    #[tokio::test]
+    async fn addresses_are_bound_once_until_closed() {
+        let network = MemoryNetwork::new();
+        let (transport, _incoming) = network.bind(addr(1)).expect("Failed to bind");
+        assert!(matches!(
+            network.bind(addr(1)),
+            Err(TransportError::AddrInUse(_))
+        ));
+
+        transport.close();
+        let (_rebound, _incoming) = network.bind(addr(1)).expect("Failed to bind again");
+        // closing the old transport again leaves the new one bound
+        transport.close();
+        assert!(matches!(
+            network.bind(addr(1)),
+            Err(TransportError::AddrInUse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn closed_transports_are_unreachable() {
+        let network = MemoryNetwork::new();
+        let (client, _incoming) = network.bind(addr(1)).expect("Failed to bind");
+        let (server, mut incoming) = network.bind(addr(2)).expect("Failed to bind");
+
+        let connection = client.connect(addr(2)).await.expect("Failed to connect");
+        connection.send(frame(b"hi")).await.expect("Failed to send");
+        let mut accepted = incoming.recv().await.expect("No connection");
+        assert_eq!(accepted.remote_addr, addr(1));
+        let msg = accepted.msgs.recv().await.expect("No msg").expect("A msg");
+        assert_eq!(msg.frame, frame(b"hi"));
+
+        server.close();
+        assert!(matches!(
+            connection.send(frame(b"hi")).await,
+            Err(TransportError::Send(_))
+        ));
+        assert!(matches!(
+            client.connect(addr(2)).await,
+            Err(TransportError::Connection(_))
+        ));
+
+        client.close();
+        assert!(matches!(
+            client.connect(addr(2)).await,
+            Err(TransportError::LocalClose)
+        ));
+        assert!(matches!(
+            connection.send(frame(b"hi")).await,
+            Err(TransportError::LocalClose)
+        ));
+    }
+
+    #[tokio::test]
+    async fn requests_get_the_frame_responded_with() {
+        let network = MemoryNetwork::new();
+        let (client, _incoming) = network.bind(addr(1)).expect("Failed to bind");
+        let (_server, mut incoming) = network.bind(addr(2)).expect("Failed to bind");
+        let _handle = tokio::spawn(async move {
+            while let Some(mut conn) = incoming.recv().await {
+                while let Some(Ok(msg)) = conn.msgs.recv().await {
+                    if let Some(stream) = msg.send_stream {
+                        let _ = stream.send(frame(b"pong")).await;
+                    }
+                }
+            }
+        });
+
+        let connection = client.connect(addr(2)).await.expect("Failed to connect");
+        let response = connection.request(frame(b"ping")).await;
+        assert_eq!(response.ok(), Some(frame(b"pong")));
+    }
+    /// Loses the first frame, and delivers the next ones twice, 1 and 2 seconds on.
+    #[derive(Debug, Default)]
+    struct LoseFirstThenDuplicate {
+        sent: AtomicU64,
+    }
+
+    impl NetworkFaults for LoseFirstThenDuplicate {
+        fn deliveries(&self, _from: SocketAddr, _to: SocketAddr, _frame: &Frame) -> Vec<Duration> {
+            match self.sent.fetch_add(1, Ordering::SeqCst) {
+                0 => vec![],
+                _ => vec![Duration::from_secs(1), Duration::from_secs(2)],
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn faults_decide_when_and_how_often_frames_are_delivered() {
+        let network = MemoryNetwork::with_faults(Arc::new(LoseFirstThenDuplicate::default()));
+        let (client, _incoming) = network.bind(addr(1)).expect("Failed to bind");
+        let (_server, mut incoming) = network.bind(addr(2)).expect("Failed to bind");
+
+        let connection = client.connect(addr(2)).await.expect("Failed to connect");
+        connection
+            .send(frame(b"lost"))
+            .await
+            .expect("Failed to send");
+        let start = tokio::time::Instant::now();
+        connection
+            .send(frame(b"twice"))
+            .await
+            .expect("Failed to send");
+
+        let mut accepted = incoming.recv().await.expect("No connection");
+        for delay in [1, 2] {
+            let msg = accepted.msgs.recv().await.expect("No msg").expect("A msg");
+            assert_eq!(msg.frame, frame(b"twice"));
+            assert_eq!(start.elapsed(), Duration::from_secs(delay));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_the_first_copy_of_a_request_gets_to_respond() {
+        let network = MemoryNetwork::with_faults(Arc::new(LoseFirstThenDuplicate::default()));
+        let (client, _incoming) = network.bind(addr(1)).expect("Failed to bind");
+        let (_server, mut incoming) = network.bind(addr(2)).expect("Failed to bind");
+        let _handle = tokio::spawn(async move {
+            while let Some(mut conn) = incoming.recv().await {
+                let mut responses = [frame(b"first"), frame(b"second")].into_iter();
+                while let Some(Ok(msg)) = conn.msgs.recv().await {
+                    if let (Some(stream), Some(response)) = (msg.send_stream, responses.next()) {
+                        let _ = stream.send(response).await;
+                    }
+                }
+            }
+        });
+
+        let connection = client.connect(addr(2)).await.expect("Failed to connect");
+        // the lost request is never responded to
+        let lost =
+            tokio::time::timeout(Duration::from_secs(10), connection.request(frame(b"ping")));
+        assert!(lost.await.is_err());
+        let response = connection.request(frame(b"ping")).await;
+        assert_eq!(response.ok(), Some(frame(b"first")));
+    }
+}
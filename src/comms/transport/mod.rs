@@ -0,0 +1,139 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! The network layer comms run on.
+//!
+//! `Comm` only needs to open connections, send frames on them, make bidi requests
+//! and be told of incoming connections. `Qp2pTransport` does so over QUIC,
+//! `MemoryTransport` over in-process channels, so that whole clusters can run in one process.
+
+mod memory;
+mod quic;
+
+pub use self::memory::{MemoryNetwork, MemoryTransport, NetworkFaults};
+pub use self::quic::Qp2pTransport;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{fmt, net::SocketAddr, sync::Arc};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+/// Connections opened to us by other nodes.
+pub type IncomingConnections = mpsc::Receiver<IncomingConnection>;
+
+/// Opens connections to other nodes.
+#[async_trait]
+pub trait Transport: fmt::Debug + Send + Sync + 'static {
+    /// The address other nodes reach us at.
+    fn local_addr(&self) -> SocketAddr;
+
+    /// Opens a new connection to the node at the passed in address.
+    async fn connect(&self, addr: SocketAddr) -> Result<Arc<dyn Connection>, TransportError>;
+
+    /// Stops accepting connections, and fails any further use of the transport.
+    fn close(&self);
+}
+
+/// A connection we opened to another node.
+#[async_trait]
+pub trait Connection: Send + Sync {
+    fn id(&self) -> String;
+
+    fn remote_addr(&self) -> SocketAddr;
+
+    /// Sends the frame, without waiting for any response.
+    async fn send(&self, frame: Frame) -> Result<(), TransportError>;
+
+    /// Sends the frame on a new bidi stream, and waits for the response on it.
+    async fn request(&self, frame: Frame) -> Result<Frame, TransportError>;
+}
+
+/// The sending half of a bidi stream opened by another node, to respond on.
+#[async_trait]
+pub trait ResponseStream: Send {
+    fn id(&self) -> String;
+
+    /// Sends the response, and finishes the stream.
+    async fn send(self: Box<Self>, frame: Frame) -> Result<(), TransportError>;
+}
+
+/// What goes over the wire for a msg: its header, its destination and its payload,
+/// kept apart so that the header and destination can be read without decoding the payload.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub header: Bytes,
+    pub dst: Bytes,
+    pub payload: Bytes,
+}
+
+impl Frame {
+    /// Number of bytes of the frame, over all its parts.
+    pub fn len(&self) -> usize {
+        self.header.len() + self.dst.len() + self.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A connection another node opened to us.
+#[derive(Debug)]
+pub struct IncomingConnection {
+    pub id: String,
+    pub remote_addr: SocketAddr,
+    /// Msgs coming in on the connection, until it is closed.
+    pub msgs: mpsc::Receiver<Result<IncomingMsg, TransportError>>,
+}
+
+/// A frame received on a connection.
+#[derive(Debug)]
+pub struct IncomingMsg {
+    pub frame: Frame,
+    /// The stream to respond on, if the msg came on a bidi stream.
+    pub send_stream: Option<SendStream>,
+}
+
+/// Stream to send the response to a msg on.
+pub struct SendStream(Box<dyn ResponseStream>);
+
+impl SendStream {
+    pub fn new(stream: impl ResponseStream + 'static) -> Self {
+        Self(Box::new(stream))
+    }
+
+    pub fn id(&self) -> String {
+        self.0.id()
+    }
+
+    pub async fn send(self, frame: Frame) -> Result<(), TransportError> {
+        self.0.send(frame).await
+    }
+}
+
+impl fmt::Debug for SendStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SendStream({})", self.id())
+    }
+}
+
+/// Errors of the underlying transport.
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("Address {0} is already in use")]
+    AddrInUse(SocketAddr),
+    #[error("Failed to connect: {0}")]
+    Connection(String),
+    #[error("Failed to send: {0}")]
+    Send(String),
+    #[error("Failed to receive: {0}")]
+    Recv(String),
+    #[error("Connection was closed on our side")]
+    LocalClose,
+}
@@ -0,0 +1,189 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{
+    Connection, Frame, IncomingConnection, IncomingConnections, IncomingMsg, ResponseStream,
+    SendStream, Transport, TransportError,
+};
+
+use async_trait::async_trait;
+use qp2p::{
+    Close, ConnectionError, ConnectionIncoming, Endpoint, EndpointError, SendError, UsrMsgBytes,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{sync::mpsc, task};
+use tracing::{trace, warn};
+
+/// Number of connections and msgs per connection buffered until the listener picks them up.
+const INCOMING_CHANNEL_SIZE: usize = 1_000;
+
+/// QUIC transport, over a qp2p endpoint.
+#[derive(Debug, Clone)]
+pub struct Qp2pTransport {
+    endpoint: Endpoint,
+}
+
+impl Qp2pTransport {
+    /// Binds an endpoint to the passed in address, and starts accepting connections on it.
+    pub fn new(local_addr: SocketAddr) -> Result<(Self, IncomingConnections), EndpointError> {
+        let (endpoint, mut incoming_conns) = Endpoint::builder().addr(local_addr).server()?;
+
+        let (sender, receiver) = mpsc::channel(INCOMING_CHANNEL_SIZE);
+        let _handle = task::spawn(async move {
+            while let Some((connection, incoming_msgs)) = incoming_conns.next().await {
+                let (msgs_sender, msgs) = mpsc::channel(INCOMING_CHANNEL_SIZE);
+                let incoming = IncomingConnection {
+                    id: connection.id(),
+                    remote_addr: connection.remote_address(),
+                    msgs,
+                };
+                let _handle = task::spawn(forward_msgs(incoming_msgs, msgs_sender));
+                if sender.send(incoming).await.is_err() {
+                    trace!("Nobody listens to incoming connections anymore");
+                    break;
+                }
+            }
+        });
+
+        Ok((Self { endpoint }, receiver))
+    }
+}
+
+#[async_trait]
+impl Transport for Qp2pTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.endpoint.local_addr()
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<Arc<dyn Connection>, TransportError> {
+        let (conn, _) = self
+            .endpoint
+            .connect_to(&addr)
+            .await
+            .map_err(connection_error)?;
+        Ok(Arc::new(Qp2pConnection(conn)))
+    }
+
+    fn close(&self) {
+        self.endpoint.close()
+    }
+}
+
+struct Qp2pConnection(qp2p::Connection);
+
+#[async_trait]
+impl Connection for Qp2pConnection {
+    fn id(&self) -> String {
+        self.0.id()
+    }
+
+    fn remote_addr(&self) -> SocketAddr {
+        self.0.remote_address()
+    }
+
+    async fn send(&self, frame: Frame) -> Result<(), TransportError> {
+        self.0
+            .send_with(frame.into_parts(), 0 /* priority */)
+            .await
+            .map_err(send_error)
+    }
+
+    async fn request(&self, frame: Frame) -> Result<Frame, TransportError> {
+        let (mut send_stream, recv_stream) = self.0.open_bi().await.map_err(connection_error)?;
+
+        let stream_id = send_stream.id();
+        send_stream.set_priority(10);
+        send_stream
+            .send_user_msg(frame.into_parts())
+            .await
+            .map_err(send_error)?;
+
+        // unblock + move finish off thread as it's not strictly related to the sending of the msg.
+        let _handle = task::spawn(async move {
+            // Attempt to gracefully terminate the stream.
+            // If this errors it does _not_ mean our message has not been sent
+            let result = send_stream.finish().await;
+            trace!("Finished {stream_id}: {result:?}");
+        });
+
+        match recv_stream.read().await {
+            Ok(parts) => Ok(Frame::from_parts(parts)),
+            Err(error) => Err(TransportError::Recv(format!("{error:?}"))),
+        }
+    }
+}
+
+struct Qp2pResponseStream(qp2p::SendStream);
+
+#[async_trait]
+impl ResponseStream for Qp2pResponseStream {
+    fn id(&self) -> String {
+        self.0.id().to_string()
+    }
+
+    async fn send(mut self: Box<Self>, frame: Frame) -> Result<(), TransportError> {
+        self.0
+            .send_user_msg(frame.into_parts())
+            .await
+            .map_err(send_error)?;
+        self.0.finish().await.map_err(send_error)
+    }
+}
+
+/// Hands the msgs of a qp2p connection over to the listener.
+async fn forward_msgs(
+    mut incoming_msgs: ConnectionIncoming,
+    msgs: mpsc::Sender<Result<IncomingMsg, TransportError>>,
+) {
+    while let Some(result) = incoming_msgs.next_with_stream().await.transpose() {
+        let msg = match result {
+            Ok((wire_msg, send_stream)) => Ok(IncomingMsg {
+                frame: Frame::from_parts(wire_msg.0),
+                send_stream: send_stream.map(|stream| SendStream::new(Qp2pResponseStream(stream))),
+            }),
+            Err(error) => {
+                warn!("Error on incoming connection: {error:?}");
+                Err(TransportError::Recv(format!("{error:?}")))
+            }
+        };
+        if msgs.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
+impl Frame {
+    /// The frame as qp2p's header, dst and payload slots.
+    fn into_parts(self) -> UsrMsgBytes {
+        (self.header, self.dst, self.payload)
+    }
+
+    fn from_parts((header, dst, payload): UsrMsgBytes) -> Self {
+        Self {
+            header,
+            dst,
+            payload,
+        }
+    }
+}
+
+fn connection_error(error: ConnectionError) -> TransportError {
+    match error {
+        ConnectionError::Closed(Close::Local) => TransportError::LocalClose,
+        error => TransportError::Connection(format!("{error:?}")),
+    }
+}
+
+fn send_error(error: SendError) -> TransportError {
+    match error {
+        SendError::ConnectionLost(ConnectionError::Closed(Close::Local)) => {
+            TransportError::LocalClose
+        }
+        error => TransportError::Send(format!("{error:?}")),
+    }
+}
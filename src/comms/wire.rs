@@ -0,0 +1,242 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Framing of the bytes on the wire.
+//!
+//! Every frame carries a header of [`HEADER_LEN`] bytes:
+//!
+//! | bytes    | field                                              |
+//! |----------|----------------------------------------------------|
+//! | `0..4`   | the magic bytes, [`MAGIC`]                         |
+//! | `4..6`   | the protocol version of the payload, big-endian    |
+//! | `6`      | the kind of frame                                  |
+//! | `7..15`  | the msg id, big-endian                             |
+//! | `15..47` | the sender's id                                    |
+//!
+//! The kind is one of a msg (`0`), a handshake (`1`), a msg to be acked (`2`), or an ack (`3`).
+//!
+//! The destination of a frame comes along separately: the recipient's 32 byte id, or nothing
+//! for a frame meant for anyone. The header and destination layouts and the handshake must stay
+//! as they are in later versions, so that nodes can agree on a version to speak, and route frames
+//! without decoding them.
+//!
+//! The payload of a msg frame is the bincode encoded `NetworkMsg`, whose body is either a payload
+//! or an error response. Its signature covers the whole msg, along with the version and kind
+//! of the frame. A handshake frame carries a bincode encoded [`Handshake`], which nodes send
+//! first over each connection they open. A msg sent to be acked is acked by a frame of its own,
+//! carrying the id of the msg and nothing else.
+
+use super::{transport::Frame, Error, MsgId, NodeId, Result};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// Marks the start of our frames, telling them apart from anything else sent our way.
+pub const MAGIC: [u8; 4] = *b"SSNT";
+
+/// The protocol version we speak, and encode our msgs with.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The oldest protocol version we still understand.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Length of the header: magic bytes, version, frame kind, msg id and sender.
+pub const HEADER_LEN: usize = MAGIC.len() + 2 + 1 + 8 + 32;
+
+/// What a frame carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FrameKind {
+    /// A `NetworkMsg`.
+    Msg,
+    /// A `Handshake`.
+    Handshake,
+    /// A `NetworkMsg` to be acked once received.
+    ReliableMsg,
+    /// The ack of a reliable msg.
+    Ack,
+}
+
+impl FrameKind {
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Self::Msg => 0,
+            Self::Handshake => 1,
+            Self::ReliableMsg => 2,
+            Self::Ack => 3,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Msg),
+            1 => Some(Self::Handshake),
+            2 => Some(Self::ReliableMsg),
+            3 => Some(Self::Ack),
+            _ => None,
+        }
+    }
+}
+
+/// The header of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub(crate) version: u16,
+    pub(crate) kind: FrameKind,
+    pub(crate) msg_id: MsgId,
+    /// The sender, as claimed: only the signature of a msg proves it.
+    pub(crate) src: NodeId,
+}
+
+impl Header {
+    /// A header for a frame of our own protocol version.
+    pub(crate) fn ours(kind: FrameKind, msg_id: MsgId, src: NodeId) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            kind,
+            msg_id,
+            src,
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(HEADER_LEN);
+        buf.put_slice(&MAGIC);
+        buf.put_u16(self.version);
+        buf.put_u8(self.kind.tag());
+        buf.put_u64(self.msg_id.0);
+        buf.put_slice(self.src.as_bytes());
+        buf.freeze()
+    }
+
+    pub(crate) fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != HEADER_LEN || bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        bytes.advance(MAGIC.len());
+        let version = bytes.get_u16();
+        let kind = FrameKind::from_tag(bytes.get_u8()).ok_or(Error::InvalidHeader)?;
+        let msg_id = MsgId(bytes.get_u64());
+        let mut src = [0; 32];
+        bytes.copy_to_slice(&mut src);
+        Ok(Self {
+            version,
+            kind,
+            msg_id,
+            src: NodeId::from_bytes(src),
+        })
+    }
+
+    /// Whether the frame carries a `NetworkMsg`.
+    pub(crate) fn is_msg(&self) -> bool {
+        matches!(self.kind, FrameKind::Msg | FrameKind::ReliableMsg)
+    }
+
+    /// Whether we understand the payload of the frame.
+    pub(crate) fn is_supported(&self) -> bool {
+        supported_versions().contains(&self.version)
+    }
+}
+
+/// The destination slot of a frame: the recipient's id, or nothing for a frame meant for anyone.
+pub(crate) fn dst_to_bytes(dst: Option<NodeId>) -> Bytes {
+    match dst {
+        Some(dst) => Bytes::copy_from_slice(dst.as_bytes()),
+        None => Bytes::new(),
+    }
+}
+
+pub(crate) fn dst_from_bytes(bytes: &[u8]) -> Result<Option<NodeId>> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    let dst = bytes.try_into().map_err(|_| Error::InvalidHeader)?;
+    Ok(Some(NodeId::from_bytes(dst)))
+}
+
+/// Whether the frame carries a msg to be acked by its recipient once received.
+pub(crate) fn is_reliable(frame: &Frame) -> bool {
+    matches!(Header::from_bytes(&frame.header), Ok(header) if header.kind == FrameKind::ReliableMsg)
+}
+
+/// Our ack of the reliable msg `msg_id` from `dst`.
+pub fn ack(msg_id: MsgId, src: NodeId, dst: NodeId) -> Frame {
+    Frame {
+        header: Header::ours(FrameKind::Ack, msg_id, src).to_bytes(),
+        dst: dst_to_bytes(Some(dst)),
+        payload: Bytes::new(),
+    }
+}
+
+/// Whether the frame is the ack of the reliable msg `msg_id` by `src`.
+pub fn is_ack(frame: &Frame, msg_id: MsgId, src: NodeId) -> bool {
+    match Header::from_bytes(&frame.header) {
+        Ok(header) => header.kind == FrameKind::Ack && header.msg_id == msg_id && header.src == src,
+        Err(_) => false,
+    }
+}
+
+/// The protocol versions we understand.
+pub(crate) fn supported_versions() -> RangeInclusive<u16> {
+    MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION
+}
+
+/// The highest version within both ranges, if any.
+pub(crate) fn negotiate(ours: RangeInclusive<u16>, theirs: RangeInclusive<u16>) -> Option<u16> {
+    let highest = *ours.end().min(theirs.end());
+    let lowest = *ours.start().max(theirs.start());
+    (highest >= lowest).then_some(highest)
+}
+
+/// Agreeing on the protocol version, run by a node over the first connection it opens to another.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handshake {
+    /// The versions the connecting node speaks.
+    Hello { min_version: u16, max_version: u16 },
+    /// The highest version both nodes speak, which the connection is to use.
+    Accept { version: u16 },
+    /// The node speaks none of the connecting node's versions, only these.
+    Refuse { min_version: u16, max_version: u16 },
+}
+
+impl Handshake {
+    /// Our opening of the handshake.
+    pub fn hello() -> Self {
+        Self::Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Our answer to the hello of another node.
+    pub fn answer(min_version: u16, max_version: u16) -> Self {
+        match negotiate(supported_versions(), min_version..=max_version) {
+            Some(version) => Self::Accept { version },
+            None => Self::Refuse {
+                min_version: MIN_PROTOCOL_VERSION,
+                max_version: PROTOCOL_VERSION,
+            },
+        }
+    }
+
+    pub fn to_frame(&self, src: NodeId, dst: Option<NodeId>) -> Result<Frame> {
+        Ok(Frame {
+            header: Header::ours(FrameKind::Handshake, MsgId::new(), src).to_bytes(),
+            dst: dst_to_bytes(dst),
+            payload: bincode::serialize(self)?.into(),
+        })
+    }
+
+    /// Decodes the handshake, which looks the same in any version.
+    pub fn from_frame(frame: &Frame) -> Result<Self> {
+        match Header::from_bytes(&frame.header)?.kind {
+            FrameKind::Handshake => Ok(bincode::deserialize(&frame.payload)?),
+            _ => Err(Error::InvalidHeader),
+        }
+    }
+}
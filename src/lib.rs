@@ -0,0 +1,2 @@
+pub mod comms;
+pub mod stableset;
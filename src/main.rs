@@ -1,50 +1,80 @@
-mod comms;
-mod stableset;
-
-use crate::comms::{Comm, NetworkNode};
-use crate::stableset::{run_stable_set, StableSetMsg};
+use stableset_net::comms::{Comm, Keypair, NetworkNode};
+use stableset_net::stableset::{join_stable_set, run_stable_set, StableSetMsg};
 
 use std::collections::BTreeSet;
 use std::{env, fs, net::SocketAddr};
+use tokio::time::{timeout, Duration};
+use tracing::{error, info, warn};
 
 const PEERS_CONFIG_FILE: &str = "peers.json";
 
-/// Read my addr from env var and peers addr from config file
-fn get_config() -> (SocketAddr, BTreeSet<SocketAddr>) {
+/// How long we wait for the other members to let us leave, before shutting down anyway.
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read my addr and secret key from env vars and peers from config file,
+/// along with whether we are one of those peers.
+/// Without a NODE_KEY we get a fresh identity, which is fine for nodes joining the set.
+fn get_config() -> (SocketAddr, Keypair, BTreeSet<NetworkNode>, bool) {
     let my_addr_str: String = env::var("NODE_ADDR").expect("Failed to read NODE_ADDR from env");
     let my_addr = my_addr_str.parse().expect("Unable to parse socket address");
+    let keypair = match env::var("NODE_KEY") {
+        Ok(secret) => Keypair::from_hex(&secret).expect("Unable to parse NODE_KEY"),
+        Err(_) => Keypair::random(),
+    };
     let peers_json =
         fs::read_to_string(PEERS_CONFIG_FILE).expect("Unable to read peers config file");
-    let peers_ip_str: Vec<String> =
+    let genesis: BTreeSet<NetworkNode> =
         serde_json::from_str(&peers_json).expect("Unable to parse peers config file");
-    let peers_addr: BTreeSet<SocketAddr> = peers_ip_str
-        .iter()
-        .filter(|p| *p != &my_addr_str)
-        .map(|p| p.parse().expect("Unable to parse socket address"))
+    let is_genesis = genesis.iter().any(|p| p.id == keypair.id());
+    let peers: BTreeSet<NetworkNode> = genesis
+        .into_iter()
+        .filter(|p| p.id != keypair.id())
         .collect();
-    println!("Read Peers from config: {:?}", peers_addr);
-    (my_addr, peers_addr)
+    info!("Read Peers from config: {:?}", peers);
+    (my_addr, keypair, peers, is_genesis)
 }
 
-/// start node and no_return unless fatal error
-async fn start_node(my_addr: SocketAddr, peers_addrs: BTreeSet<SocketAddr>) {
-    println!("Starting comms for node {my_addr:?}");
-    let peers = peers_addrs
-        .into_iter()
-        .map(|p| NetworkNode { addr: p })
-        .collect();
+/// start node and run it until ctrl-c is hit
+/// nodes which are not part of the genesis set join through the configured peers
+async fn start_node(
+    my_addr: SocketAddr,
+    keypair: Keypair,
+    peers: BTreeSet<NetworkNode>,
+    is_genesis: bool,
+) {
+    info!("Starting comms for node {} at {my_addr:?}", keypair.id());
+
+    let (sender, receiver) = Comm::new::<StableSetMsg>(keypair, my_addr).expect("Comms Failed");
 
-    let (sender, receiver) = Comm::new::<StableSetMsg>(my_addr).expect("Comms Failed");
+    let node = if is_genesis {
+        info!("Run stable set with peers {peers:?}");
+        run_stable_set(sender, receiver, peers)
+    } else {
+        info!("Join stable set through peers {peers:?}");
+        join_stable_set(sender, receiver, peers)
+    };
 
-    println!("Run stable set with peers {peers:?}");
-    run_stable_set(sender, receiver, peers).await
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for ctrl-c");
+    info!("Leaving...");
+    match timeout(LEAVE_TIMEOUT, node.leave()).await {
+        Ok(Ok(snapshot)) => info!("Left the set: {snapshot:?}"),
+        Ok(Err(error)) => warn!("Failed to leave: {error}"),
+        Err(_elapsed) => warn!("Timed out leaving the set"),
+    }
+    info!("Shutting down...");
+    match node.shutdown().await {
+        Ok(snapshot) => info!("Final membership: {snapshot:?}"),
+        Err(error) => error!("Node failed to shut down: {error}"),
+    }
 }
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let (my_addr, peers_addr) = get_config();
+    let (my_addr, keypair, peers, is_genesis) = get_config();
 
-    start_node(my_addr, peers_addr).await;
+    start_node(my_addr, keypair, peers, is_genesis).await;
 }
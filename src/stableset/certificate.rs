@@ -0,0 +1,144 @@
+use crate::comms::{NetworkNode, NodeId};
+
+use super::{membership::contains_id, Ballot, Change};
+
+use ed25519_dalek::Signature;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Proof that a change was decided by the members of the set at `generation`.
+///
+/// It holds the signed commits of a supermajority of those members, all cast in the same round,
+/// so anyone knowing the members at `generation` can check the change without trusting the sender.
+/// Chained from a known set, certificates prove what the current set is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuorumCert {
+    /// The generation the change was applied on top of.
+    pub generation: u64,
+    /// The round the change was decided in.
+    pub round: u64,
+    pub change: Change,
+    pub signatures: BTreeMap<NodeId, Signature>,
+}
+
+impl QuorumCert {
+    /// Whether a supermajority of the passed in members, expected to be the set at `generation`,
+    /// signed the change.
+    pub fn verify(&self, members: &BTreeSet<NetworkNode>) -> bool {
+        let bytes = signed_bytes(self.generation, self.round, &Ballot::Commit(self.change));
+        let valid = self
+            .signatures
+            .iter()
+            .filter(|(id, signature)| contains_id(members, id) && id.verify(&bytes, signature))
+            .count();
+        valid >= supermajority(members.len())
+    }
+}
+
+/// Number of commits needed for a change to be applied: strictly more than 2/3 of the members.
+pub fn supermajority(members: usize) -> usize {
+    members * 2 / 3 + 1
+}
+
+/// What members sign when casting a ballot.
+pub(crate) fn signed_bytes(generation: u64, round: u64, ballot: &Ballot) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(64);
+    bytes.extend_from_slice(&generation.to_be_bytes());
+    bytes.extend_from_slice(&round.to_be_bytes());
+    let change = match ballot {
+        Ballot::Change(change) => {
+            bytes.push(0);
+            change
+        }
+        Ballot::Commit(change) => {
+            bytes.push(1);
+            change
+        }
+        Ballot::Skip => {
+            bytes.push(2);
+            return bytes;
+        }
+    };
+    match change {
+        Change::Join(node) => {
+            bytes.push(0);
+            bytes.extend_from_slice(node.id.as_bytes());
+            bytes.extend_from_slice(node.addr.to_string().as_bytes());
+        }
+        Change::Leave(id) => {
+            bytes.push(1);
+            bytes.extend_from_slice(id.as_bytes());
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comms::Keypair;
+
+    fn keypair(secret: u8) -> Keypair {
+        Keypair::from_secret([secret; 32])
+    }
+
+    fn node(secret: u8) -> NetworkNode {
+        NetworkNode {
+            id: keypair(secret).id(),
+            addr: ([10, 0, 0, secret], 12000).into(),
+        }
+    }
+
+    /// Node 5 joining, committed in round 1 by the nodes of the passed in secrets.
+    fn cert(signers: &[u8]) -> QuorumCert {
+        let change = Change::Join(node(5));
+        let bytes = signed_bytes(3, 1, &Ballot::Commit(change));
+        QuorumCert {
+            generation: 3,
+            round: 1,
+            change,
+            signatures: signers
+                .iter()
+                .map(|secret| (keypair(*secret).id(), keypair(*secret).sign(&bytes)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn supermajorities_are_more_than_two_thirds() {
+        let needed: Vec<_> = (1..=7).map(supermajority).collect();
+        assert_eq!(needed, [1, 2, 3, 3, 4, 5, 5]);
+    }
+
+    #[test]
+    fn certs_need_a_supermajority_of_member_commits() {
+        let members: BTreeSet<_> = (1..=4).map(node).collect();
+        assert!(cert(&[1, 2, 3]).verify(&members));
+        assert!(cert(&[1, 2, 3, 4]).verify(&members));
+        assert!(!cert(&[1, 2]).verify(&members));
+        // signatures of non-members don't count
+        assert!(!cert(&[1, 2, 6, 7]).verify(&members));
+    }
+
+    #[test]
+    fn certs_only_hold_for_what_was_signed() {
+        let members: BTreeSet<_> = (1..=4).map(node).collect();
+        let mut other_round = cert(&[1, 2, 3]);
+        other_round.round = 2;
+        assert!(!other_round.verify(&members));
+
+        let mut other_generation = cert(&[1, 2, 3]);
+        other_generation.generation = 4;
+        assert!(!other_generation.verify(&members));
+
+        let mut other_change = cert(&[1, 2, 3]);
+        other_change.change = Change::Leave(node(1).id);
+        assert!(!other_change.verify(&members));
+
+        // a member's signature given under another member's id
+        let mut borrowed = cert(&[1, 2]);
+        let signature = borrowed.signatures[&node(1).id];
+        let _ = borrowed.signatures.insert(node(3).id, signature);
+        assert!(!borrowed.verify(&members));
+    }
+}
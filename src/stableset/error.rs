@@ -0,0 +1,22 @@
+// Copyright 2023 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use thiserror::Error;
+
+/// The type returned by the stable set node runtime.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Stable set error.
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[error("The node runtime has stopped")]
+    NodeStopped,
+    #[error("Comms error: {0}")]
+    Comms(#[from] crate::comms::Error),
+}
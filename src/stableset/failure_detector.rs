@@ -0,0 +1,211 @@
+use crate::comms::NodeId;
+
+use super::Liveness;
+
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Number of pings in a row a peer can miss before being suspected.
+const MAX_CONSECUTIVE_MISSES: u32 = 3;
+
+/// How long a peer stays suspected before we propose to evict it.
+const SUSPICION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout based failure detector.
+///
+/// Peers get suspected when missing pings or when comms to them fail,
+/// and cleared as soon as they answer a ping again. Peers suspected for
+/// longer than the suspicion timeout are reported for eviction, once.
+#[derive(Debug)]
+pub struct FailureDetector {
+    max_misses: u32,
+    suspicion_timeout: Duration,
+    /// Suspected peers, and since when.
+    suspects: BTreeMap<NodeId, Instant>,
+    /// Peers we already reported for eviction.
+    evicting: BTreeSet<NodeId>,
+}
+
+impl Default for FailureDetector {
+    fn default() -> Self {
+        Self::new(MAX_CONSECUTIVE_MISSES, SUSPICION_TIMEOUT)
+    }
+}
+
+impl FailureDetector {
+    pub fn new(max_misses: u32, suspicion_timeout: Duration) -> Self {
+        Self {
+            max_misses,
+            suspicion_timeout,
+            suspects: BTreeMap::new(),
+            evicting: BTreeSet::new(),
+        }
+    }
+
+    /// Suspects the node, unless it already is.
+    pub fn suspect(&mut self, node: NodeId, now: Instant) {
+        let _ = self.suspects.entry(node).or_insert_with(|| {
+            debug!("Suspecting {node:?}");
+            now
+        });
+    }
+
+    pub fn is_suspected(&self, node: &NodeId) -> bool {
+        self.suspects.contains_key(node)
+    }
+
+    /// Suspects peers which missed too many pings, and clears the ones we heard from since.
+    /// Returns the cleared peers we had already reported for eviction.
+    pub fn update(&mut self, liveness: &Liveness, now: Instant) -> BTreeSet<NodeId> {
+        let mut recovered = BTreeSet::new();
+        for node in liveness.peers().copied() {
+            let peer = match liveness.get(&node) {
+                Some(peer) => peer,
+                None => continue,
+            };
+            if peer.consecutive_misses >= self.max_misses {
+                self.suspect(node, now);
+                continue;
+            }
+            let heard_since = match (self.suspects.get(&node), peer.last_seen) {
+                (Some(since), Some(seen)) => seen > *since,
+                _ => false,
+            };
+            if heard_since && peer.consecutive_misses == 0 {
+                debug!("{node:?} is responsive again");
+                let _ = self.suspects.remove(&node);
+                if self.evicting.remove(&node) {
+                    let _ = recovered.insert(node);
+                }
+            }
+        }
+        recovered
+    }
+
+    /// Only keeps track of the passed in members.
+    pub fn retain(&mut self, members: &BTreeSet<NodeId>) {
+        self.suspects.retain(|node, _| members.contains(node));
+        self.evicting.retain(|node| members.contains(node));
+    }
+
+    /// Peers suspected for long enough to be evicted, which were not reported yet.
+    pub fn to_evict(&mut self, now: Instant) -> BTreeSet<NodeId> {
+        let timeout = self.suspicion_timeout;
+        let to_evict: BTreeSet<_> = self
+            .suspects
+            .iter()
+            .filter(|(node, since)| {
+                !self.evicting.contains(node) && now.saturating_duration_since(**since) >= timeout
+            })
+            .map(|(node, _)| *node)
+            .collect();
+        for node in &to_evict {
+            info!("{node:?} has been unresponsive for over {timeout:?}, evicting it");
+        }
+        self.evicting.extend(to_evict.iter().copied());
+        to_evict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comms::{Keypair, MsgId};
+
+    const PING_TIMEOUT: Duration = Duration::from_secs(1);
+    const SUSPICION: Duration = Duration::from_secs(5);
+
+    fn node(secret: u8) -> NodeId {
+        Keypair::from_secret([secret; 32]).id()
+    }
+
+    /// Has the peer miss a ping sent at `at`.
+    fn miss_ping(liveness: &mut Liveness, peer: NodeId, at: Instant) {
+        liveness.ping_sent(peer, MsgId::new(), at);
+        let _ = liveness.expire(at + PING_TIMEOUT, PING_TIMEOUT);
+    }
+
+    /// Has the peer answer a ping sent at `at`.
+    fn answer_ping(liveness: &mut Liveness, peer: NodeId, at: Instant) {
+        let ping = MsgId::new();
+        liveness.ping_sent(peer, ping, at);
+        assert!(liveness.pong_received(peer, ping, at));
+    }
+
+    #[test]
+    fn peers_missing_pings_are_evicted_after_the_suspicion_timeout() {
+        let peer = node(1);
+        let mut liveness = Liveness::new(&BTreeSet::from([peer]));
+        let mut detector = FailureDetector::new(2, SUSPICION);
+        let start = Instant::now();
+
+        miss_ping(&mut liveness, peer, start);
+        let _ = detector.update(&liveness, start);
+        assert!(!detector.is_suspected(&peer));
+
+        miss_ping(&mut liveness, peer, start);
+        let suspected_at = start + PING_TIMEOUT;
+        let _ = detector.update(&liveness, suspected_at);
+        assert!(detector.is_suspected(&peer));
+
+        assert!(detector.to_evict(suspected_at + SUSPICION / 2).is_empty());
+        assert_eq!(
+            detector.to_evict(suspected_at + SUSPICION),
+            BTreeSet::from([peer])
+        );
+        // reported once only
+        assert!(detector.to_evict(suspected_at + SUSPICION * 2).is_empty());
+    }
+
+    #[test]
+    fn suspicion_keeps_its_first_time() {
+        let peer = node(1);
+        let mut detector = FailureDetector::new(2, SUSPICION);
+        let start = Instant::now();
+        detector.suspect(peer, start);
+        detector.suspect(peer, start + SUSPICION / 2);
+        assert_eq!(detector.to_evict(start + SUSPICION), BTreeSet::from([peer]));
+    }
+
+    #[test]
+    fn peers_answering_again_are_cleared_and_reported_if_being_evicted() {
+        let (evicted, suspected) = (node(1), node(2));
+        let mut liveness = Liveness::new(&BTreeSet::from([evicted, suspected]));
+        let mut detector = FailureDetector::new(2, SUSPICION);
+        let start = Instant::now();
+        detector.suspect(evicted, start);
+        detector.suspect(suspected, start + SUSPICION);
+        assert_eq!(
+            detector.to_evict(start + SUSPICION),
+            BTreeSet::from([evicted])
+        );
+
+        // having been heard from before the suspicion doesn't clear it
+        answer_ping(&mut liveness, suspected, start);
+        assert!(detector.update(&liveness, start).is_empty());
+        assert!(detector.is_suspected(&suspected));
+
+        let later = start + SUSPICION * 2;
+        answer_ping(&mut liveness, evicted, later);
+        answer_ping(&mut liveness, suspected, later);
+        assert_eq!(detector.update(&liveness, later), BTreeSet::from([evicted]));
+        assert!(!detector.is_suspected(&evicted));
+        assert!(!detector.is_suspected(&suspected));
+    }
+
+    #[test]
+    fn only_members_are_tracked() {
+        let (member, gone) = (node(1), node(2));
+        let mut detector = FailureDetector::new(2, SUSPICION);
+        let start = Instant::now();
+        detector.suspect(member, start);
+        detector.suspect(gone, start);
+        detector.retain(&BTreeSet::from([member]));
+        assert!(!detector.is_suspected(&gone));
+        assert_eq!(
+            detector.to_evict(start + SUSPICION),
+            BTreeSet::from([member])
+        );
+    }
+}
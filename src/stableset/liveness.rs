@@ -0,0 +1,209 @@
+use crate::comms::{MsgId, NodeId};
+
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, trace};
+
+/// What we know of how responsive a peer is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerLiveness {
+    /// Last time we heard from the peer.
+    pub last_seen: Option<Instant>,
+    /// Round-trip time of the last answered ping.
+    pub rtt: Option<Duration>,
+    /// Number of pings in a row which went unanswered.
+    pub consecutive_misses: u32,
+}
+
+/// Tracks the liveness of peers by matching every `Ping` we send with its `Pong`.
+/// Peers are known by id, so what we know of one survives it moving to another address.
+///
+/// Time is passed in by the caller, so that the tracker can be driven by a virtual clock.
+#[derive(Debug, Default)]
+pub struct Liveness {
+    peers: BTreeMap<NodeId, PeerLiveness>,
+    /// Pings which have not been answered yet.
+    outstanding: BTreeMap<MsgId, (NodeId, Instant)>,
+}
+
+impl Liveness {
+    pub fn new(peers: &BTreeSet<NodeId>) -> Self {
+        let mut liveness = Self::default();
+        liveness.set_peers(peers);
+        liveness
+    }
+
+    /// Tracks only the passed in peers, keeping what we know of the ones we already tracked.
+    pub fn set_peers(&mut self, peers: &BTreeSet<NodeId>) {
+        self.peers.retain(|node, _| peers.contains(node));
+        for node in peers {
+            let _ = self.peers.entry(*node).or_default();
+        }
+        self.outstanding.retain(|_, (node, _)| peers.contains(node));
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &NodeId> {
+        self.peers.keys()
+    }
+
+    pub fn get(&self, node: &NodeId) -> Option<&PeerLiveness> {
+        self.peers.get(node)
+    }
+
+    /// Registers a ping sent to `node`, to be answered with a `Pong` of the same `MsgId`.
+    pub fn ping_sent(&mut self, node: NodeId, msg_id: MsgId, now: Instant) {
+        if self.peers.contains_key(&node) {
+            let _ = self.outstanding.insert(msg_id, (node, now));
+        }
+    }
+
+    /// Registers a `Pong`, returns whether it answered one of our pings.
+    pub fn pong_received(&mut self, node: NodeId, msg_id: MsgId, now: Instant) -> bool {
+        match self.outstanding.get(&msg_id) {
+            Some((pinged, sent_at)) if *pinged == node => {
+                let rtt = now.saturating_duration_since(*sent_at);
+                let _ = self.outstanding.remove(&msg_id);
+                if let Some(peer) = self.peers.get_mut(&node) {
+                    trace!("Pong from {node:?} for {msg_id:?} after {rtt:?}");
+                    peer.rtt = Some(rtt);
+                    peer.last_seen = Some(now);
+                    peer.consecutive_misses = 0;
+                }
+                true
+            }
+            _ => {
+                debug!("Unexpected pong {msg_id:?} from {node:?}");
+                false
+            }
+        }
+    }
+
+    /// Registers any other msg from `node`, as proof it is still around.
+    pub fn heard_from(&mut self, node: NodeId, now: Instant) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            peer.last_seen = Some(now);
+        }
+    }
+
+    /// Counts pings older than `timeout` as missed, returns the peers which missed one.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> BTreeSet<NodeId> {
+        let mut missed = BTreeSet::new();
+        let peers = &mut self.peers;
+        self.outstanding.retain(|msg_id, (node, sent_at)| {
+            if now.saturating_duration_since(*sent_at) < timeout {
+                return true;
+            }
+            if let Some(peer) = peers.get_mut(node) {
+                peer.consecutive_misses += 1;
+                debug!(
+                    "{node:?} missed ping {msg_id:?}, {} in a row",
+                    peer.consecutive_misses
+                );
+            }
+            let _ = missed.insert(*node);
+            false
+        });
+        missed
+    }
+
+    /// A peer is alive if it answered its last ping.
+    pub fn is_alive(&self, node: &NodeId) -> bool {
+        self.peers
+            .get(node)
+            .map(|peer| peer.rtt.is_some() && peer.consecutive_misses == 0)
+            .unwrap_or(false)
+    }
+
+    pub fn not_alive(&self) -> BTreeSet<NodeId> {
+        self.peers
+            .keys()
+            .filter(|node| !self.is_alive(node))
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comms::Keypair;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn node(secret: u8) -> NodeId {
+        Keypair::from_secret([secret; 32]).id()
+    }
+
+    #[test]
+    fn pongs_answering_our_pings_make_the_peer_alive() {
+        let (peer, other) = (node(1), node(2));
+        let mut liveness = Liveness::new(&BTreeSet::from([peer, other]));
+        let start = Instant::now();
+        assert!(!liveness.is_alive(&peer));
+
+        let ping = MsgId::new();
+        liveness.ping_sent(peer, ping, start);
+        // only the pinged node answers the ping, and only once
+        assert!(!liveness.pong_received(other, ping, start));
+        assert!(!liveness.pong_received(peer, MsgId::new(), start));
+        let rtt = Duration::from_millis(30);
+        assert!(liveness.pong_received(peer, ping, start + rtt));
+        assert!(!liveness.pong_received(peer, ping, start + rtt));
+
+        let state = liveness.get(&peer).expect("peer is tracked");
+        assert_eq!(state.rtt, Some(rtt));
+        assert_eq!(state.last_seen, Some(start + rtt));
+        assert!(liveness.is_alive(&peer));
+        assert_eq!(liveness.not_alive(), BTreeSet::from([other]));
+    }
+
+    #[test]
+    fn pings_unanswered_in_time_count_as_missed() {
+        let peer = node(1);
+        let mut liveness = Liveness::new(&BTreeSet::from([peer]));
+        let start = Instant::now();
+        let ping = MsgId::new();
+        liveness.ping_sent(peer, ping, start);
+        assert!(liveness.pong_received(peer, ping, start));
+
+        for round in 1..=2 {
+            let sent_at = start + TIMEOUT * round;
+            liveness.ping_sent(peer, MsgId::new(), sent_at);
+            assert!(liveness.expire(sent_at + TIMEOUT / 2, TIMEOUT).is_empty());
+            assert_eq!(
+                liveness.expire(sent_at + TIMEOUT, TIMEOUT),
+                BTreeSet::from([peer])
+            );
+        }
+        let state = liveness.get(&peer).expect("peer is tracked");
+        assert_eq!(state.consecutive_misses, 2);
+        assert!(!liveness.is_alive(&peer));
+
+        // being heard from is no answer to the pings
+        liveness.heard_from(peer, start + TIMEOUT * 4);
+        assert_eq!(liveness.get(&peer).map(|p| p.consecutive_misses), Some(2));
+        let ping = MsgId::new();
+        liveness.ping_sent(peer, ping, start + TIMEOUT * 4);
+        assert!(liveness.pong_received(peer, ping, start + TIMEOUT * 4));
+        assert!(liveness.is_alive(&peer));
+    }
+
+    #[test]
+    fn only_the_peers_set_are_tracked() {
+        let (kept, dropped, stranger) = (node(1), node(2), node(3));
+        let mut liveness = Liveness::new(&BTreeSet::from([kept, dropped]));
+        let start = Instant::now();
+        liveness.ping_sent(kept, MsgId::new(), start);
+        liveness.ping_sent(dropped, MsgId::new(), start);
+        liveness.ping_sent(stranger, MsgId::new(), start);
+
+        liveness.set_peers(&BTreeSet::from([kept]));
+        assert_eq!(liveness.peers().copied().collect::<Vec<_>>(), vec![kept]);
+        assert!(liveness.get(&dropped).is_none());
+        // the pings to nodes no longer tracked are forgotten
+        assert_eq!(
+            liveness.expire(start + TIMEOUT, TIMEOUT),
+            BTreeSet::from([kept])
+        );
+    }
+}
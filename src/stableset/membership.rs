@@ -0,0 +1,353 @@
+use crate::comms::{NetworkNode, NodeId};
+
+use super::QuorumCert;
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, net::SocketAddr};
+
+/// A change to the membership of the stable set.
+///
+/// Members are identified by their id, the address a node joins with is only its first known one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Change {
+    Join(NetworkNode),
+    Leave(NodeId),
+}
+
+impl Change {
+    /// Whether the change can be applied on top of the passed in members.
+    pub fn is_applicable(&self, members: &BTreeSet<NetworkNode>) -> bool {
+        match self {
+            Change::Join(node) => !contains_id(members, &node.id),
+            Change::Leave(id) => contains_id(members, id),
+        }
+    }
+}
+
+/// Whether a node with the given id is among the passed in nodes, whatever its address.
+pub fn contains_id(nodes: &BTreeSet<NetworkNode>, id: &NodeId) -> bool {
+    nodes.iter().any(|node| node.id == *id)
+}
+
+/// The membership of the set as of a given generation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipSnapshot {
+    pub generation: u64,
+    pub members: BTreeSet<NetworkNode>,
+}
+
+/// The membership of the stable set, along with the history of how it came to be.
+///
+/// Every applied change bumps the generation by one and its certificate is appended to the log,
+/// so that the set at any generation since the base one can be rebuilt,
+/// and lagging nodes can be sent only the changes they missed, which they can verify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Membership {
+    /// Where our history starts: the genesis set, or a snapshot we caught up from.
+    base: MembershipSnapshot,
+    /// Certificates of the applied changes,
+    /// the one at index `i` leading to generation `base.generation + i + 1`.
+    log: Vec<QuorumCert>,
+    members: BTreeSet<NetworkNode>,
+}
+
+impl Membership {
+    pub fn new(genesis: BTreeSet<NetworkNode>) -> Self {
+        Self::from_snapshot(MembershipSnapshot {
+            generation: 0,
+            members: genesis,
+        })
+    }
+
+    /// Starts off from a snapshot, we won't know about the generations before it.
+    pub fn from_snapshot(snapshot: MembershipSnapshot) -> Self {
+        Self {
+            members: snapshot.members.clone(),
+            base: snapshot,
+            log: vec![],
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.base.generation + self.log.len() as u64
+    }
+
+    /// The membership our history starts from.
+    pub fn base(&self) -> &MembershipSnapshot {
+        &self.base
+    }
+
+    /// The first generation we know the members of.
+    pub fn base_generation(&self) -> u64 {
+        self.base.generation
+    }
+
+    pub fn members(&self) -> &BTreeSet<NetworkNode> {
+        &self.members
+    }
+
+    pub fn is_member(&self, id: &NodeId) -> bool {
+        contains_id(&self.members, id)
+    }
+
+    /// The member with the given id, at its latest known address.
+    pub fn get(&self, id: &NodeId) -> Option<&NetworkNode> {
+        self.members.iter().find(|node| node.id == *id)
+    }
+
+    /// Records the new address of a member, returns whether it had moved.
+    /// This does not change the membership, so the generation stays the same.
+    pub fn update_addr(&mut self, id: &NodeId, addr: SocketAddr) -> bool {
+        let node = match self.get(id) {
+            Some(node) if node.addr != addr => *node,
+            _ => return false,
+        };
+        let _ = self.members.remove(&node);
+        let _ = self.members.insert(NetworkNode { id: *id, addr });
+        true
+    }
+
+    pub fn snapshot(&self) -> MembershipSnapshot {
+        MembershipSnapshot {
+            generation: self.generation(),
+            members: self.members.clone(),
+        }
+    }
+
+    /// Certificates of the applied changes, in order, starting from the base generation.
+    pub fn log(&self) -> &[QuorumCert] {
+        &self.log
+    }
+
+    /// Rebuilds the membership from a trusted starting point, such as the genesis set,
+    /// and the certificates of the changes applied since.
+    /// Returns `None` if any of the certificates fails verification.
+    pub fn verify_chain(base: MembershipSnapshot, certs: &[QuorumCert]) -> Option<Self> {
+        let generation = base.generation;
+        let mut membership = Self::from_snapshot(base);
+        if membership.apply_delta(generation, certs) != certs.len() {
+            return None;
+        }
+        Some(membership)
+    }
+
+    /// Applies the certified change, returning the new generation.
+    /// Changes which were not decided at our generation or which don't apply
+    /// to the current members are refused. The certificate itself is trusted,
+    /// use `apply_delta` for certificates received from others.
+    pub fn apply(&mut self, cert: QuorumCert) -> Option<u64> {
+        if cert.generation != self.generation() || !cert.change.is_applicable(&self.members) {
+            return None;
+        }
+        apply_to(&mut self.members, cert.change);
+        self.log.push(cert);
+        Some(self.generation())
+    }
+
+    /// What the set was at the given generation, if we know of it.
+    pub fn members_at(&self, generation: u64) -> Option<BTreeSet<NetworkNode>> {
+        let certs = self.certs_since(generation)?;
+        let mut members = self.base.members.clone();
+        for cert in &self.log[..self.log.len() - certs.len()] {
+            apply_to(&mut members, cert.change);
+        }
+        Some(members)
+    }
+
+    /// Certificates of the changes applied after the given generation, if we know of them all.
+    pub fn certs_since(&self, generation: u64) -> Option<&[QuorumCert]> {
+        if generation < self.base.generation || generation > self.generation() {
+            return None;
+        }
+        let from = (generation - self.base.generation) as usize;
+        Some(&self.log[from..])
+    }
+
+    /// Applies the certified changes sent to us, which were applied on top of `generation`.
+    /// Changes we already have are skipped, and we stop at the first certificate
+    /// not signed by a supermajority of the members it was decided by.
+    /// Returns how many were applied.
+    pub fn apply_delta(&mut self, generation: u64, certs: &[QuorumCert]) -> usize {
+        let ours = self.generation();
+        if generation > ours {
+            return 0;
+        }
+        let known = (ours - generation) as usize;
+        certs
+            .iter()
+            .skip(known)
+            .take_while(|cert| cert.verify(&self.members) && self.apply((*cert).clone()).is_some())
+            .count()
+    }
+}
+
+fn apply_to(members: &mut BTreeSet<NetworkNode>, change: Change) {
+    match change {
+        Change::Join(node) => {
+            let _ = members.insert(node);
+        }
+        Change::Leave(id) => members.retain(|node| node.id != id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comms::Keypair,
+        stableset::{certificate::signed_bytes, Ballot},
+    };
+
+    fn keypair(secret: u8) -> Keypair {
+        Keypair::from_secret([secret; 32])
+    }
+
+    fn node(secret: u8) -> NetworkNode {
+        NetworkNode {
+            id: keypair(secret).id(),
+            addr: ([10, 0, 0, secret], 12000).into(),
+        }
+    }
+
+    /// The first `count` nodes, which start off the set.
+    fn genesis(count: u8) -> BTreeSet<NetworkNode> {
+        (1..=count).map(node).collect()
+    }
+
+    /// The change decided at `generation`, committed by the nodes of the passed in secrets.
+    fn cert(signers: &[u8], generation: u64, change: Change) -> QuorumCert {
+        let bytes = signed_bytes(generation, 0, &Ballot::Commit(change));
+        QuorumCert {
+            generation,
+            round: 0,
+            change,
+            signatures: signers
+                .iter()
+                .map(|secret| (keypair(*secret).id(), keypair(*secret).sign(&bytes)))
+                .collect(),
+        }
+    }
+
+    /// Has node 5 join the genesis set of 4, then node 1 leave, then node 6 join.
+    fn history() -> Vec<QuorumCert> {
+        vec![
+            cert(&[1, 2, 3], 0, Change::Join(node(5))),
+            cert(&[2, 3, 4, 5], 1, Change::Leave(node(1).id)),
+            cert(&[2, 3, 4], 2, Change::Join(node(6))),
+        ]
+    }
+
+    #[test]
+    fn applied_changes_bump_the_generation_and_are_logged() {
+        let mut membership = Membership::new(genesis(4));
+        let [join, leave, _] = <[QuorumCert; 3]>::try_from(history()).expect("three certs");
+
+        assert_eq!(membership.apply(join.clone()), Some(1));
+        // decided at another generation, or not applicable to the members
+        assert_eq!(membership.apply(join.clone()), None);
+        assert_eq!(membership.apply(cert(&[], 1, Change::Join(node(5)))), None);
+        assert_eq!(
+            membership.apply(cert(&[], 1, Change::Leave(node(9).id))),
+            None
+        );
+        assert_eq!(membership.apply(leave.clone()), Some(2));
+
+        assert_eq!(membership.generation(), 2);
+        assert_eq!(membership.log(), &[join, leave][..]);
+        assert!(!membership.is_member(&node(1).id));
+        assert!(membership.is_member(&node(5).id));
+    }
+
+    #[test]
+    fn past_members_and_certs_are_known_from_the_base_on() {
+        let mut membership = Membership::new(genesis(4));
+        for cert in history() {
+            assert!(membership.apply(cert).is_some());
+        }
+
+        assert_eq!(membership.members_at(0), Some(genesis(4)));
+        let mut second = genesis(4);
+        let _ = second.insert(node(5));
+        let _ = second.remove(&node(1));
+        assert_eq!(membership.members_at(2), Some(second));
+        assert_eq!(
+            membership.members_at(3).as_ref(),
+            Some(membership.members())
+        );
+        assert_eq!(membership.members_at(4), None);
+
+        assert_eq!(membership.certs_since(0).map(<[_]>::len), Some(3));
+        assert_eq!(membership.certs_since(2), Some(&history()[2..]));
+        assert_eq!(membership.certs_since(3).map(<[_]>::len), Some(0));
+        assert_eq!(membership.certs_since(4), None);
+
+        // history before a snapshot is unknown
+        let caught_up = Membership::from_snapshot(membership.snapshot());
+        assert_eq!(caught_up.base_generation(), 3);
+        assert_eq!(caught_up.members_at(2), None);
+        assert_eq!(caught_up.certs_since(2), None);
+        assert_eq!(caught_up.members_at(3).as_ref(), Some(membership.members()));
+    }
+
+    #[test]
+    fn deltas_skip_known_changes_and_stop_at_bad_certs() {
+        let certs = history();
+        let mut membership = Membership::new(genesis(4));
+        assert!(membership.apply(certs[0].clone()).is_some());
+        assert_eq!(membership.apply_delta(0, &certs), 2);
+        assert_eq!(membership.generation(), 3);
+
+        // a gap between our generation and the first cert
+        let mut lagging = Membership::new(genesis(4));
+        assert_eq!(lagging.apply_delta(1, &certs[1..]), 0);
+        assert_eq!(lagging.generation(), 0);
+
+        // short of a supermajority of the members, or signed by non-members
+        let bad = [
+            cert(&[1, 2], 0, Change::Join(node(5))),
+            cert(&[1, 2, 7], 0, Change::Join(node(5))),
+        ];
+        for bad in bad {
+            let certs = [bad, certs[1].clone()];
+            assert_eq!(lagging.apply_delta(0, &certs), 0);
+        }
+        // a cert signed for another change
+        let mut forged = certs[0].clone();
+        forged.change = Change::Join(node(6));
+        assert_eq!(lagging.apply_delta(0, &[forged]), 0);
+
+        // the good certs before a bad one are kept
+        let mut tampered = certs.clone();
+        tampered[1].round += 1;
+        assert_eq!(lagging.apply_delta(0, &tampered), 1);
+        assert_eq!(lagging.generation(), 1);
+    }
+
+    #[test]
+    fn chains_are_verified_cert_by_cert_from_their_base() {
+        let base = Membership::new(genesis(4)).snapshot();
+        let certs = history();
+        let verified = Membership::verify_chain(base.clone(), &certs).expect("A valid chain");
+        assert_eq!(verified.generation(), 3);
+        assert_eq!(verified.log(), &certs[..]);
+
+        assert!(Membership::verify_chain(base.clone(), &certs[1..]).is_none());
+        let mut tampered = certs.clone();
+        tampered[2].change = Change::Join(node(7));
+        assert!(Membership::verify_chain(base, &tampered).is_none());
+    }
+
+    #[test]
+    fn moved_members_keep_the_generation() {
+        let mut membership = Membership::new(genesis(4));
+        let moved: SocketAddr = ([10, 0, 1, 1], 12000).into();
+        assert!(membership.update_addr(&node(1).id, moved));
+        assert!(!membership.update_addr(&node(1).id, moved));
+        assert!(!membership.update_addr(&node(9).id, moved));
+        assert_eq!(
+            membership.get(&node(1).id).map(|node| node.addr),
+            Some(moved)
+        );
+        assert_eq!(membership.generation(), 0);
+    }
+}
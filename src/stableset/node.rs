@@ -0,0 +1,465 @@
+use crate::comms::{self, Comm, CommEvent, MsgId, NetworkNode, NodeId, SendOptions};
+
+use super::{
+    Change, Error, FailureDetector, Liveness, Membership, MembershipSnapshot, Outgoing, Result,
+    StableSet, StableSetMsg,
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{interval, timeout, Duration, Instant, MissedTickBehavior},
+};
+use tracing::{debug, error, info};
+
+type Rx = mpsc::Receiver<CommEvent<StableSetMsg>>;
+
+/// How often we ping our peers and re-send our state to the other members.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a ping can go unanswered before it is counted as missed.
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a leaving node waits for its last msgs to be sent before closing its endpoint.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Capacity of the channel the application sends cmds on.
+const CMD_CHANNEL_SIZE: usize = 100;
+
+/// Cmds the application can send to a running node.
+#[derive(Debug)]
+pub enum NodeCmd {
+    /// Returns the current membership.
+    Snapshot(oneshot::Sender<MembershipSnapshot>),
+    /// Returns the current membership along with the certified history we know of.
+    Membership(oneshot::Sender<Membership>),
+    /// Leaves the set, then stops the node.
+    /// Returns the membership we left behind, once our leave has been witnessed.
+    Leave(oneshot::Sender<MembershipSnapshot>),
+    /// Stops the node, closing its endpoint.
+    Shutdown,
+}
+
+/// Handle to a running node, used by the application to drive it.
+#[derive(Debug)]
+pub struct NodeHandle {
+    cmd_sender: mpsc::Sender<NodeCmd>,
+    handle: JoinHandle<MembershipSnapshot>,
+}
+
+impl NodeHandle {
+    /// The current membership as seen by the node.
+    pub async fn snapshot(&self) -> Result<MembershipSnapshot> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_cmd(NodeCmd::Snapshot(sender)).await?;
+        receiver.await.map_err(|_| Error::NodeStopped)
+    }
+
+    /// The current membership and the certificates of the changes which led to it.
+    pub async fn membership(&self) -> Result<Membership> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_cmd(NodeCmd::Membership(sender)).await?;
+        receiver.await.map_err(|_| Error::NodeStopped)
+    }
+
+    /// Asks the members to let us leave, returning the membership without us once they did.
+    /// The node then stops on its own, `shutdown` can still be used to wait for it.
+    pub async fn leave(&self) -> Result<MembershipSnapshot> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_cmd(NodeCmd::Leave(sender)).await?;
+        receiver.await.map_err(|_| Error::NodeStopped)
+    }
+
+    /// Stops the node gracefully, returning the final membership.
+    pub async fn shutdown(self) -> Result<MembershipSnapshot> {
+        // the node may have stopped on its own already, in which case we just collect its snapshot
+        let _ = self.send_cmd(NodeCmd::Shutdown).await;
+        self.handle.await.map_err(|_| Error::NodeStopped)
+    }
+
+    async fn send_cmd(&self, cmd: NodeCmd) -> Result<()> {
+        self.cmd_sender
+            .send(cmd)
+            .await
+            .map_err(|_| Error::NodeStopped)
+    }
+}
+
+/// A node taking part in the stable set.
+///
+/// The node is an actor: it owns all of its state and reacts to msgs from the network,
+/// to its timer and to cmds from the application, one at a time.
+pub struct Node {
+    comm: Comm,
+    comm_events: Rx,
+    stable_set: StableSet,
+    liveness: Liveness,
+    failure_detector: FailureDetector,
+    everyone_alive: bool,
+    /// Pings sent to members at another address than the one we know them at, and when.
+    /// Any msg can be replayed from anywhere, so a member only moves once it answers there.
+    moves: BTreeMap<MsgId, (NetworkNode, Instant)>,
+    /// Members we ask to let us in, until we are part of the set.
+    contacts: BTreeSet<NetworkNode>,
+    /// Set while we are leaving the set, to report back once we're out.
+    leaving: Option<oneshot::Sender<MembershipSnapshot>>,
+}
+
+impl Node {
+    /// The genesis set is made of the passed in peers and ourselves.
+    pub fn new(comm: Comm, comm_events: Rx, peers: BTreeSet<NetworkNode>) -> Self {
+        let our_node = comm.our_node();
+        comm.set_comm_targets(peers.clone());
+        let liveness = Liveness::new(&ids(&peers));
+        let mut genesis = peers;
+        let _ = genesis.insert(our_node);
+        let stable_set = StableSet::new(comm.keypair().clone(), our_node.addr, genesis);
+
+        Self {
+            comm,
+            comm_events,
+            stable_set,
+            liveness,
+            failure_detector: FailureDetector::default(),
+            everyone_alive: false,
+            moves: BTreeMap::new(),
+            contacts: BTreeSet::new(),
+            leaving: None,
+        }
+    }
+
+    /// A node joining an existing set, by asking the passed in members to let it in.
+    /// They are taken to be the genesis set, which the history we are sent is verified from.
+    pub fn join(comm: Comm, comm_events: Rx, contacts: BTreeSet<NetworkNode>) -> Self {
+        let stable_set =
+            StableSet::joining(comm.keypair().clone(), comm.socket_addr(), contacts.clone());
+        comm.set_comm_targets(contacts.clone());
+
+        Self {
+            comm,
+            comm_events,
+            stable_set,
+            liveness: Liveness::new(&ids(&contacts)),
+            failure_detector: FailureDetector::default(),
+            everyone_alive: false,
+            moves: BTreeMap::new(),
+            contacts,
+            leaving: None,
+        }
+    }
+
+    /// Spawns the node runtime.
+    pub fn start(self) -> NodeHandle {
+        let (cmd_sender, cmd_receiver) = mpsc::channel(CMD_CHANNEL_SIZE);
+        let handle = tokio::spawn(self.run(cmd_receiver));
+        NodeHandle { cmd_sender, handle }
+    }
+
+    async fn run(mut self, mut cmds: mpsc::Receiver<NodeCmd>) -> MembershipSnapshot {
+        let mut ticker = interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            // biased, so that runs over a simulated network can be reproduced
+            let result = tokio::select! {
+                biased;
+                _ = ticker.tick() => self.handle_tick(),
+                cmd = cmds.recv() => match cmd {
+                    Some(NodeCmd::Snapshot(sender)) => {
+                        let _ = sender.send(self.stable_set.snapshot());
+                        Ok(())
+                    }
+                    Some(NodeCmd::Membership(sender)) => {
+                        let _ = sender.send(self.stable_set.membership().clone());
+                        Ok(())
+                    }
+                    Some(NodeCmd::Leave(sender)) => self.leave(sender),
+                    Some(NodeCmd::Shutdown) | None => break,
+                },
+                event = self.comm_events.recv() => match event {
+                    Some(event) => self.handle_comm_event(event),
+                    None => {
+                        info!("Comms stopped, stopping stable set");
+                        break;
+                    }
+                },
+            };
+
+            if let Err(error) = result {
+                error!("Stable set node failed to handle event: {error}");
+            }
+            if self.leaving.is_some() && !self.is_joined() {
+                info!(
+                    "Left the set at generation {}",
+                    self.stable_set.generation()
+                );
+                if let Some(sender) = self.leaving.take() {
+                    let _ = sender.send(self.stable_set.snapshot());
+                }
+                // the other members may still be waiting on our commit of the leave
+                if timeout(FLUSH_TIMEOUT, self.comm.flush()).await.is_err() {
+                    debug!("Closing the endpoint with msgs still unsent");
+                }
+                break;
+            }
+        }
+
+        debug!("Shutting down node {:?}", self.stable_set.id());
+        self.comm.close_endpoint();
+        self.stable_set.snapshot()
+    }
+
+    fn handle_tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let missed = self.liveness.expire(now, PING_TIMEOUT);
+        if !missed.is_empty() {
+            debug!("Peers which missed a ping: {missed:?}");
+        }
+        self.moves
+            .retain(|_, (_, sent)| now.duration_since(*sent) < PING_TIMEOUT);
+        self.ping_all_peers(now)?;
+
+        if !self.is_joined() {
+            // keeps up with the set, in case we were evicted from it
+            let msgs = self.stable_set.tick();
+            self.send_msgs(msgs)?;
+            return self.request_join();
+        }
+
+        let generation = self.stable_set.generation();
+        let mut msgs = self.stable_set.tick();
+        for node in self.failure_detector.update(&self.liveness, now) {
+            info!("{node:?} is responsive again, no longer evicting it");
+            self.stable_set.withdraw(&Change::Leave(node));
+        }
+        for node in self.failure_detector.to_evict(now) {
+            info!("Proposing to evict unresponsive {node:?}");
+            msgs.extend(self.stable_set.propose(Change::Leave(node)));
+        }
+        if self.stable_set.generation() != generation {
+            self.on_membership_change();
+        }
+        self.send_msgs(msgs)
+    }
+
+    fn handle_comm_event(&mut self, event: CommEvent<StableSetMsg>) -> Result<()> {
+        let msg = match event {
+            CommEvent::Msg(msg) => msg,
+            CommEvent::Error { node_id, error } => {
+                debug!("Comms error with {node_id:?}: {error}");
+                // anyone can forge a msg in the name of a member, that says nothing of its liveness
+                let forged = matches!(
+                    error,
+                    comms::Error::InvalidSignature(_)
+                        | comms::Error::Misdirected(_)
+                        | comms::Error::MessageTooLarge { .. }
+                );
+                // a node responding with an error is alive, whatever the error
+                let responded = matches!(error, comms::Error::ErrorResponse(_));
+                if !forged && !responded && self.stable_set.is_member(&node_id.id) {
+                    self.failure_detector.suspect(node_id.id, Instant::now());
+                }
+                return Ok(());
+            }
+        };
+
+        let src = msg.sender;
+        let msg_id = msg.wire_msg.id;
+        // we sign all our msgs, so an unsigned one can't be trusted to come from `src`
+        if !msg.wire_msg.is_signed() {
+            debug!("Dropping unsigned msg {msg_id:?} claiming to be from {src:?}");
+            return Ok(());
+        }
+        let moved = self
+            .stable_set
+            .membership()
+            .get(&src.id)
+            .is_some_and(|member| member.addr != src.addr);
+        if moved {
+            let answered = matches!(msg.wire_msg.body, Ok(StableSetMsg::Pong))
+                && self
+                    .moves
+                    .get(&msg_id)
+                    .is_some_and(|(node, _)| *node == src);
+            if answered {
+                let _ = self.moves.remove(&msg_id);
+                if self.stable_set.update_addr(src) {
+                    info!("{:?} moved to {:?}", src.id, src.addr);
+                    self.on_peers_change();
+                }
+                return Ok(());
+            }
+            self.probe_move(src)?;
+        }
+
+        let payload = match msg.wire_msg.body {
+            Ok(payload) => payload,
+            Err(error) => {
+                debug!("Dropping error {error} from {src:?}, as we sent no request to respond to");
+                return Ok(());
+            }
+        };
+
+        match payload {
+            StableSetMsg::Ping => {
+                if !moved {
+                    self.liveness.heard_from(src.id, Instant::now());
+                }
+                self.send_msg(src, msg_id, StableSetMsg::Pong)
+            }
+            // pongs to our pings come from where we sent them
+            StableSetMsg::Pong if moved => Ok(()),
+            StableSetMsg::Pong => {
+                let _ = self.liveness.pong_received(src.id, msg_id, Instant::now());
+                if !self.everyone_alive && self.liveness.not_alive().is_empty() {
+                    self.everyone_alive = true;
+                    info!("Everyone is alive! {:?}", self.peers());
+                }
+                Ok(())
+            }
+            // the msg is signed, so it can be handled wherever it comes from,
+            // but a replay says nothing of the liveness of its sender
+            stableset_msg => {
+                if !moved {
+                    self.liveness.heard_from(src.id, Instant::now());
+                }
+                let generation = self.stable_set.generation();
+                let msgs = self.stable_set.handle_msg(src, stableset_msg);
+                if self.stable_set.generation() != generation {
+                    self.on_membership_change();
+                }
+                self.send_msgs(msgs)
+            }
+        }
+    }
+
+    fn on_membership_change(&mut self) {
+        info!(
+            "Generation {}, members: {:?}",
+            self.stable_set.generation(),
+            self.stable_set.members()
+        );
+        if !self.contacts.is_empty() && self.is_joined() {
+            info!(
+                "Joined the set at generation {}",
+                self.stable_set.generation()
+            );
+            self.contacts.clear();
+        }
+        self.on_peers_change();
+    }
+
+    fn on_peers_change(&mut self) {
+        let peers = self.peers();
+        self.comm.set_comm_targets(peers.clone());
+        self.liveness.set_peers(&ids(&peers));
+        self.failure_detector
+            .retain(&ids(self.stable_set.members()));
+    }
+
+    /// Proposes our own leave, and asks the other members to witness it.
+    fn leave(&mut self, sender: oneshot::Sender<MembershipSnapshot>) -> Result<()> {
+        let our_id = self.stable_set.id();
+        info!("Asking the members to let us leave");
+        self.leaving = Some(sender);
+
+        let generation = self.stable_set.generation();
+        let mut msgs: Outgoing = self
+            .peers()
+            .into_iter()
+            .map(|peer| (peer, StableSetMsg::ReqLeave(our_id)))
+            .collect();
+        msgs.extend(self.stable_set.propose(Change::Leave(our_id)));
+        if self.stable_set.generation() != generation {
+            self.on_membership_change();
+        }
+        self.send_msgs(msgs)
+    }
+
+    fn is_joined(&self) -> bool {
+        self.stable_set.is_member(&self.stable_set.id())
+    }
+
+    /// Asks our contacts to let us in, they will witness our join among the members.
+    fn request_join(&mut self) -> Result<()> {
+        let our_node = self.stable_set.node();
+        debug!("Asking {:?} to let us join", self.contacts);
+        let msgs = self
+            .contacts
+            .iter()
+            .map(|contact| (*contact, StableSetMsg::ReqJoin(our_node)))
+            .collect();
+        self.send_msgs(msgs)
+    }
+
+    /// Everyone in the set but us.
+    fn peers(&self) -> BTreeSet<NetworkNode> {
+        let our_id = self.stable_set.id();
+        self.stable_set
+            .members()
+            .iter()
+            .filter(|node| node.id != our_id)
+            .copied()
+            .collect()
+    }
+
+    fn ping_all_peers(&mut self, now: Instant) -> Result<()> {
+        for p in self.peers() {
+            let msg_id = MsgId::new();
+            self.liveness.ping_sent(p.id, msg_id, now);
+            self.send_msg(p, msg_id, StableSetMsg::Ping)?;
+        }
+        Ok(())
+    }
+
+    /// Pings the member at the address it was heard from, unless we already are.
+    fn probe_move(&mut self, node: NetworkNode) -> Result<()> {
+        if self.moves.values().any(|(probed, _)| *probed == node) {
+            return Ok(());
+        }
+        debug!(
+            "{:?} was heard from at {:?}, checking it moved",
+            node.id, node.addr
+        );
+        let msg_id = MsgId::new();
+        let _ = self.moves.insert(msg_id, (node, Instant::now()));
+        self.send_msg(node, msg_id, StableSetMsg::Ping)
+    }
+
+    fn send_msgs(&self, msgs: Outgoing) -> Result<()> {
+        for (node, payload) in msgs {
+            let id = match &payload {
+                // a ballot is sent again each tick, under the same id, so that a copy still unacked
+                // isn't sent twice, and a copy already received isn't handed on again
+                StableSetMsg::Witness(witness) => {
+                    MsgId::of(&(witness.signature.to_bytes(), node.id))
+                }
+                _ => MsgId::new(),
+            };
+            self.send_msg(node, id, payload)?;
+        }
+        Ok(())
+    }
+
+    fn send_msg(&self, node: NetworkNode, id: MsgId, payload: StableSetMsg) -> Result<()> {
+        // a lost witness may leave a change short of its quorum, so they are sent until acked
+        let (msg, options) = match payload {
+            StableSetMsg::Witness(_) => (
+                self.comm.signed_reliable_msg(id, node.id, payload)?,
+                SendOptions::reliable(),
+            ),
+            _ => (
+                self.comm.signed_msg(id, node.id, payload)?,
+                SendOptions::default(),
+            ),
+        };
+        self.comm
+            .send_out_frame(node, msg.id, msg.to_frame()?, options);
+        Ok(())
+    }
+}
+
+fn ids(nodes: &BTreeSet<NetworkNode>) -> BTreeSet<NodeId> {
+    nodes.iter().map(|node| node.id).collect()
+}
@@ -0,0 +1,849 @@
+use crate::comms::{Keypair, NetworkNode, NodeId};
+
+use super::{
+    certificate::{signed_bytes, supermajority},
+    Change, Membership, MembershipSnapshot, QuorumCert, StableSetMsg,
+};
+
+use ed25519_dalek::Signature;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+};
+use tracing::{debug, trace};
+
+/// Number of ticks a round can go on for before we give up on it,
+/// when some members don't vote and the others don't all vote the same way.
+const ROUND_TIMEOUT_TICKS: u32 = 5;
+
+/// What a member votes for in a round.
+///
+/// A round goes in two steps: members first witness the change they would see applied.
+/// Once they see a supermajority witnessed the same change, they commit to it,
+/// or once they see no change can gather a supermajority, they skip the round.
+/// A change is decided when a supermajority of the members committed to it in the same round.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Ballot {
+    /// Witnesses the change.
+    Change(Change),
+    /// Commits to a change a supermajority witnessed in the round.
+    Commit(Change),
+    /// No change can be committed to in the round.
+    Skip,
+}
+
+/// A member's signed ballot, in a round of voting on the change to apply on top of `generation`.
+///
+/// Every member witnesses at most one change, then commits or skips once, per `(generation, round)`.
+/// Members who saw a supermajority witness a change lock on it, and witness nothing else
+/// in later rounds, unless they see a supermajority witness another change in a later round.
+/// So once a change is decided, no other change can gather a supermajority in that generation,
+/// even if some members lie about their votes.
+/// The signed commits which decided a change make up its certificate.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Witness {
+    pub generation: u64,
+    pub round: u64,
+    pub ballot: Ballot,
+    pub signature: Signature,
+}
+
+impl Witness {
+    pub fn new(keypair: &Keypair, generation: u64, round: u64, ballot: Ballot) -> Self {
+        Self {
+            generation,
+            round,
+            ballot,
+            signature: keypair.sign(&signed_bytes(generation, round, &ballot)),
+        }
+    }
+
+    /// Whether the witness was signed by the node with the given id.
+    pub fn verify(&self, id: &NodeId) -> bool {
+        let bytes = signed_bytes(self.generation, self.round, &self.ballot);
+        id.verify(&bytes, &self.signature)
+    }
+}
+
+/// Outgoing msgs produced by the stable set, to be sent out by the caller.
+pub type Outgoing = Vec<(NetworkNode, StableSetMsg)>;
+
+/// Ballots cast in a generation, per round and member.
+type Ballots = BTreeMap<u64, BTreeMap<NodeId, Witness>>;
+
+/// The membership state of a node taking part in the stable set.
+///
+/// Members propose joins and leaves and witness each other's proposals.
+/// A change is applied once a supermajority of the current members committed to it,
+/// at which point the generation is bumped and voting starts over.
+/// The signed commits which decided a change are kept as its certificate.
+/// This type does no IO, the msgs it returns are to be sent out by the caller.
+#[derive(Debug, Clone)]
+pub struct StableSet {
+    /// Our own identity and address.
+    node: NetworkNode,
+    keypair: Keypair,
+    membership: Membership,
+    /// The round we currently vote in, within this generation.
+    round: u64,
+    /// Ticks since the current round started.
+    round_ticks: u32,
+    /// Witnessed changes seen in this generation.
+    witnesses: Ballots,
+    /// Commits and skips seen in this generation.
+    commits: Ballots,
+    /// The change a supermajority witnessed in the latest round we know of, and that round.
+    lock: Option<(u64, Change)>,
+    /// Requested changes which have not been decided yet.
+    pending: BTreeSet<Change>,
+}
+
+impl StableSet {
+    /// Starts off from a genesis set, which is expected to be the same on every member.
+    pub fn new(keypair: Keypair, addr: SocketAddr, genesis: BTreeSet<NetworkNode>) -> Self {
+        Self {
+            node: NetworkNode {
+                id: keypair.id(),
+                addr,
+            },
+            keypair,
+            membership: Membership::new(genesis),
+            round: 0,
+            round_ticks: 0,
+            witnesses: BTreeMap::new(),
+            commits: BTreeMap::new(),
+            lock: None,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    /// Starts off outside of the set, waiting to be let in by its members.
+    /// The history of the set we are sent must start from the genesis set.
+    pub fn joining(keypair: Keypair, addr: SocketAddr, genesis: BTreeSet<NetworkNode>) -> Self {
+        Self::new(keypair, addr, genesis)
+    }
+
+    pub fn node(&self) -> NetworkNode {
+        self.node
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.node.id
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.membership.generation()
+    }
+
+    pub fn members(&self) -> &BTreeSet<NetworkNode> {
+        self.membership.members()
+    }
+
+    pub fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    pub fn snapshot(&self) -> MembershipSnapshot {
+        self.membership.snapshot()
+    }
+
+    pub fn is_member(&self, id: &NodeId) -> bool {
+        self.membership.is_member(id)
+    }
+
+    /// Records the address a member answered us from, returns whether it had moved.
+    pub fn update_addr(&mut self, node: NetworkNode) -> bool {
+        self.membership.update_addr(&node.id, node.addr)
+    }
+
+    /// Number of commits needed for a change to be applied: strictly more than 2/3 of the members.
+    pub fn supermajority(&self) -> usize {
+        supermajority(self.members().len())
+    }
+
+    /// Proposes a change to the set, witnessing it ourselves if we haven't voted yet.
+    pub fn propose(&mut self, change: Change) -> Outgoing {
+        if !self.is_applicable(&change) {
+            trace!(
+                "Ignoring proposal of {change:?} at generation {}",
+                self.generation()
+            );
+            return vec![];
+        }
+        let _ = self.pending.insert(change);
+        self.progress()
+    }
+
+    /// Stops pushing for a change we proposed, if it is still pending.
+    /// Votes already cast for it still count, but we won't vote for it in later rounds
+    /// unless it is needed to break a split vote.
+    pub fn withdraw(&mut self, change: &Change) {
+        if self.pending.remove(change) {
+            debug!("Withdrew {change:?} at generation {}", self.generation());
+        }
+    }
+
+    /// Handles a stable set msg from `sender`, returning the msgs to send out in reaction.
+    pub fn handle_msg(&mut self, sender: NetworkNode, msg: StableSetMsg) -> Outgoing {
+        match msg {
+            StableSetMsg::ReqJoin(node) => {
+                // nodes can only ask for themselves to join
+                if self.is_member(&self.node.id) && node == sender {
+                    self.propose(Change::Join(node))
+                } else {
+                    vec![]
+                }
+            }
+            StableSetMsg::ReqLeave(id) => {
+                // nodes can only ask for themselves to leave, evictions are up to each member
+                if self.is_member(&self.node.id) && id == sender.id {
+                    self.propose(Change::Leave(id))
+                } else {
+                    vec![]
+                }
+            }
+            StableSetMsg::Witness(witness) => self.handle_witness(sender, witness),
+            StableSetMsg::SyncReq { generation } => self.handle_sync_req(sender, generation),
+            StableSetMsg::Sync { generation, certs } => self.handle_sync(sender, generation, certs),
+            StableSetMsg::SyncSnapshot { base, certs } => {
+                self.handle_sync_snapshot(sender, base, certs)
+            }
+            StableSetMsg::Ping | StableSetMsg::Pong => vec![],
+        }
+    }
+
+    /// Re-sends our ballots of this generation and our generation to every other member,
+    /// so that lost msgs don't stall the protocol, and gives up on the round if it takes too long.
+    pub fn tick(&mut self) -> Outgoing {
+        let mut msgs = self.broadcast(self.sync_req_msg());
+        for ballots in [&self.witnesses, &self.commits] {
+            for witness in ballots
+                .values()
+                .filter_map(|votes| votes.get(&self.node.id))
+            {
+                msgs.extend(self.broadcast(StableSetMsg::Witness(*witness)));
+            }
+        }
+        self.round_ticks += 1;
+        if self.round_ticks >= ROUND_TIMEOUT_TICKS {
+            msgs.extend(self.time_out_round());
+        }
+        msgs
+    }
+
+    fn handle_witness(&mut self, sender: NetworkNode, witness: Witness) -> Outgoing {
+        if witness.generation < self.generation() {
+            debug!(
+                "{sender:?} is behind at generation {}, syncing it",
+                witness.generation
+            );
+            return self.handle_sync_req(sender, witness.generation);
+        }
+        if witness.generation > self.generation() {
+            debug!(
+                "{sender:?} is ahead at generation {}, asking for sync",
+                witness.generation
+            );
+            return vec![(sender, self.sync_req_msg())];
+        }
+        if !self.is_member(&sender.id) {
+            debug!("Ignoring witness from non member {sender:?}");
+            return vec![];
+        }
+        if witness.round > self.round + 1 {
+            // ballots of later rounds are sent again once we get there,
+            // storing them now would let faulty members fill our memory with made up rounds
+            trace!(
+                "Ignoring witness from {sender:?} for round {}, as we are at round {}",
+                witness.round,
+                self.round
+            );
+            return vec![];
+        }
+        if !witness.verify(&sender.id) {
+            debug!("Ignoring witness from {sender:?} with an invalid signature");
+            return vec![];
+        }
+
+        let ballots = match witness.ballot {
+            Ballot::Change(_) => &mut self.witnesses,
+            Ballot::Commit(_) | Ballot::Skip => &mut self.commits,
+        };
+        let round_ballots = ballots.entry(witness.round).or_default();
+        if round_ballots.contains_key(&sender.id) {
+            // members cast each ballot once per round, anything else is a duplicate or a lie
+            return vec![];
+        }
+        let _ = round_ballots.insert(sender.id, witness);
+
+        if let Ballot::Change(change) = witness.ballot {
+            // only pushing for changes some honest member is behind,
+            // so that faulty members can't have the set evict honest ones
+            if self.is_applicable(&change) && self.is_backed(&change) {
+                let _ = self.pending.insert(change);
+            }
+        }
+        self.progress()
+    }
+
+    /// Sends the sender what it missed, or asks for what we missed.
+    fn handle_sync_req(&self, sender: NetworkNode, generation: u64) -> Outgoing {
+        let ours = self.generation();
+        if generation > ours {
+            return vec![(sender, self.sync_req_msg())];
+        }
+        if generation == ours {
+            return vec![];
+        }
+        let msg = match self.membership.certs_since(generation) {
+            Some(certs) => StableSetMsg::Sync {
+                generation,
+                certs: certs.to_vec(),
+            },
+            // we don't have history that far back
+            None => self.sync_snapshot_msg(),
+        };
+        vec![(sender, msg)]
+    }
+
+    fn handle_sync(
+        &mut self,
+        sender: NetworkNode,
+        generation: u64,
+        certs: Vec<QuorumCert>,
+    ) -> Outgoing {
+        let ours = self.generation();
+        if generation > ours {
+            // there is a gap between what we have and what we were sent
+            return vec![(sender, self.sync_req_msg())];
+        }
+        if self.membership.apply_delta(generation, &certs) == 0 {
+            return vec![];
+        }
+        debug!(
+            "Caught up from generation {ours} to {} thanks to {sender:?}",
+            self.generation()
+        );
+        self.new_generation()
+    }
+
+    fn handle_sync_snapshot(
+        &mut self,
+        sender: NetworkNode,
+        base: MembershipSnapshot,
+        certs: Vec<QuorumCert>,
+    ) -> Outgoing {
+        let ours = self.generation();
+        if base.generation + certs.len() as u64 <= ours {
+            return vec![];
+        }
+        // the certs vouch for the history, whoever passes it on,
+        // as long as it starts from a set we know of, addresses aside
+        let ids = |members: &BTreeSet<NetworkNode>| -> BTreeSet<NodeId> {
+            members.iter().map(|node| node.id).collect()
+        };
+        let known = self.membership.members_at(base.generation);
+        if known.map(|members| ids(&members)) != Some(ids(&base.members)) {
+            debug!(
+                "Ignoring snapshot from {sender:?} starting from an unknown set at generation {}",
+                base.generation
+            );
+            return vec![];
+        }
+        let membership = match Membership::verify_chain(base, &certs) {
+            Some(membership) => membership,
+            None => {
+                debug!("Ignoring snapshot from {sender:?} with an invalid certificate");
+                return vec![];
+            }
+        };
+        debug!(
+            "Catching up from generation {ours} to {} from a snapshot of {sender:?}",
+            membership.generation()
+        );
+        self.membership = membership;
+        self.new_generation()
+    }
+
+    /// Casts the ballots the votes seen so far allow for, moving on to the next rounds
+    /// until a change is decided or we have to wait for more votes.
+    fn progress(&mut self) -> Outgoing {
+        let mut msgs = vec![];
+        loop {
+            if let Some((round, change)) = self.decided() {
+                msgs.extend(self.apply(round, change));
+                return msgs;
+            }
+            self.update_lock();
+            match self.next_ballot() {
+                Some(ballot) => msgs.extend(self.cast(ballot)),
+                None => return msgs,
+            }
+        }
+    }
+
+    /// The ballot we are to cast next in the current round, if any,
+    /// moving on to the next round once this one is over.
+    fn next_ballot(&mut self) -> Option<Ballot> {
+        if !self.is_member(&self.node.id) {
+            return None;
+        }
+        if self.our_ballot(&self.witnesses).is_none() {
+            return self.change_to_witness().map(Ballot::Change);
+        }
+        if self.our_ballot(&self.commits).is_none() {
+            if let Some(change) = self.witnessed_by_supermajority(self.round) {
+                return Some(Ballot::Commit(change));
+            }
+            return self.is_split().then_some(Ballot::Skip);
+        }
+        if !self.is_round_over() {
+            return None;
+        }
+        self.next_round();
+        self.change_to_witness().map(Ballot::Change)
+    }
+
+    /// Skips the current round if we haven't committed to anything in it yet,
+    /// or moves on to the next one if a supermajority committed or skipped.
+    /// Any change decided in the round is carried over by the members locked on it.
+    fn time_out_round(&mut self) -> Outgoing {
+        if self.our_ballot(&self.witnesses).is_none() {
+            return vec![];
+        }
+        if self.our_ballot(&self.commits).is_none() {
+            debug!(
+                "Round {} of generation {} timed out, skipping it",
+                self.round,
+                self.generation()
+            );
+            let mut msgs = self.cast(Ballot::Skip);
+            msgs.extend(self.progress());
+            return msgs;
+        }
+        let cast = self.commits.get(&self.round).map_or(0, |votes| votes.len());
+        if cast < self.supermajority() {
+            return vec![];
+        }
+        self.next_round();
+        self.progress()
+    }
+
+    fn next_round(&mut self) {
+        self.round += 1;
+        self.round_ticks = 0;
+        debug!(
+            "No change committed to at generation {}, moving to round {}",
+            self.generation(),
+            self.round
+        );
+    }
+
+    /// The change to witness in the current round.
+    fn change_to_witness(&self) -> Option<Change> {
+        // the change we are locked on may have been decided by others
+        if let Some((_, change)) = self.lock {
+            return Some(change);
+        }
+        if self.round > 0 {
+            // the smallest change some honest member is behind in the last round,
+            // or the smallest one seen, so that members converge on the same one
+            let counts = self.counts(&self.witnesses, self.round - 1);
+            let max_faulty = self.max_faulty();
+            let smallest = counts
+                .iter()
+                .find(|(_, count)| **count > max_faulty)
+                .or_else(|| counts.iter().next())
+                .map(|(change, _)| *change);
+            if smallest.is_some() {
+                return smallest;
+            }
+        }
+        // favour a change others already witnessed, to converge faster
+        let seen = self.witnesses.get(&self.round).and_then(|votes| {
+            votes
+                .values()
+                .filter_map(|witness| match witness.ballot {
+                    Ballot::Change(change) => Some(change),
+                    _ => None,
+                })
+                .find(|change| self.pending.contains(change))
+        });
+        seen.or_else(|| self.pending.iter().next().copied())
+    }
+
+    fn cast(&mut self, ballot: Ballot) -> Outgoing {
+        trace!(
+            "{:?} casting {ballot:?} at generation {} round {}",
+            self.node,
+            self.generation(),
+            self.round
+        );
+        let witness = Witness::new(&self.keypair, self.generation(), self.round, ballot);
+        let ballots = match ballot {
+            Ballot::Change(_) => &mut self.witnesses,
+            Ballot::Commit(_) | Ballot::Skip => &mut self.commits,
+        };
+        let _ = ballots
+            .entry(self.round)
+            .or_default()
+            .insert(self.node.id, witness);
+        self.broadcast(StableSetMsg::Witness(witness))
+    }
+
+    /// Locks on the change a supermajority witnessed in the latest round, if later than our lock.
+    fn update_lock(&mut self) {
+        let latest = self
+            .witnesses
+            .keys()
+            .rev()
+            .find_map(|round| Some((*round, self.witnessed_by_supermajority(*round)?)));
+        if let Some((round, change)) = latest {
+            if self.lock.is_none_or(|(locked, _)| round > locked) {
+                trace!("Locking on {change:?} witnessed in round {round}");
+                self.lock = Some((round, change));
+            }
+        }
+    }
+
+    /// A change committed to by a supermajority of members in any round of this generation,
+    /// along with that round.
+    fn decided(&self) -> Option<(u64, Change)> {
+        let threshold = self.supermajority();
+        self.commits.keys().find_map(|round| {
+            self.counts(&self.commits, *round)
+                .into_iter()
+                .find(|(_, count)| *count >= threshold)
+                .map(|(change, _)| (*round, change))
+        })
+    }
+
+    /// The change witnessed by a supermajority of members in the given round, if any.
+    fn witnessed_by_supermajority(&self, round: u64) -> Option<Change> {
+        let threshold = self.supermajority();
+        self.counts(&self.witnesses, round)
+            .into_iter()
+            .find(|(_, count)| *count >= threshold)
+            .map(|(change, _)| change)
+    }
+
+    /// The certificate of a change decided in the given round, made of the members' commits.
+    fn certificate(&self, round: u64, change: Change) -> QuorumCert {
+        let signatures = self
+            .commits
+            .get(&round)
+            .into_iter()
+            .flatten()
+            .filter(|(voter, witness)| {
+                witness.ballot == Ballot::Commit(change) && self.is_member(voter)
+            })
+            .map(|(voter, witness)| (*voter, witness.signature))
+            .collect();
+        QuorumCert {
+            generation: self.generation(),
+            round,
+            change,
+            signatures,
+        }
+    }
+
+    /// Whether the witnesses of the current round can no longer gather a supermajority.
+    fn is_split(&self) -> bool {
+        self.cannot_reach_supermajority(&self.witnesses)
+    }
+
+    /// Whether the commits of the current round can no longer gather a supermajority.
+    fn is_round_over(&self) -> bool {
+        self.cannot_reach_supermajority(&self.commits)
+    }
+
+    fn cannot_reach_supermajority(&self, ballots: &Ballots) -> bool {
+        let cast = ballots.get(&self.round).map_or(0, |votes| votes.len());
+        let missing = self.members().len().saturating_sub(cast);
+        let threshold = self.supermajority();
+        // counting a change nobody voted for, so that a round of skips is only over
+        // once enough members cast them
+        cast > 0
+            && self
+                .counts(ballots, self.round)
+                .values()
+                .chain([&0])
+                .all(|count| count + missing < threshold)
+    }
+
+    /// Number of members which witnessed or committed to each change, in the given round.
+    fn counts(&self, ballots: &Ballots, round: u64) -> BTreeMap<Change, usize> {
+        let mut counts = BTreeMap::new();
+        for (voter, witness) in ballots.get(&round).into_iter().flatten() {
+            if let (Ballot::Change(change) | Ballot::Commit(change), true) =
+                (witness.ballot, self.is_member(voter))
+            {
+                *counts.entry(change).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Whether more members witnessed the change in this generation than can be faulty,
+    /// in which case at least one of them is honest.
+    fn is_backed(&self, change: &Change) -> bool {
+        let witnesses: BTreeSet<_> = self
+            .witnesses
+            .values()
+            .flatten()
+            .filter(|(voter, witness)| {
+                witness.ballot == Ballot::Change(*change) && self.is_member(voter)
+            })
+            .map(|(voter, _)| voter)
+            .collect();
+        witnesses.len() > self.max_faulty()
+    }
+
+    /// Number of faulty members the set tolerates, that is the members a supermajority can do without.
+    fn max_faulty(&self) -> usize {
+        self.members().len() - self.supermajority()
+    }
+
+    fn apply(&mut self, round: u64, change: Change) -> Outgoing {
+        let cert = self.certificate(round, change);
+        if self.membership.apply(cert).is_none() {
+            // we only count votes for applicable changes, so this should not happen
+            debug!("Decided {change:?} does not apply to {:?}", self.members());
+            return vec![];
+        }
+        let _ = self.pending.remove(&change);
+        debug!(
+            "{:?} applied {change:?}, now at generation {} with members {:?}",
+            self.node,
+            self.generation(),
+            self.members()
+        );
+
+        // let the joining node know about its new membership
+        let mut msgs = match change {
+            Change::Join(node) if node.id != self.node.id => {
+                vec![(node, self.sync_snapshot_msg())]
+            }
+            _ => vec![],
+        };
+        msgs.extend(self.new_generation());
+        msgs
+    }
+
+    /// Starts voting afresh, after the generation changed.
+    fn new_generation(&mut self) -> Outgoing {
+        self.round = 0;
+        self.round_ticks = 0;
+        self.witnesses.clear();
+        self.commits.clear();
+        self.lock = None;
+        let members = self.membership.members();
+        self.pending.retain(|change| change.is_applicable(members));
+        self.progress()
+    }
+
+    fn our_ballot(&self, ballots: &Ballots) -> Option<Witness> {
+        ballots
+            .get(&self.round)
+            .and_then(|votes| votes.get(&self.node.id))
+            .copied()
+    }
+
+    fn is_applicable(&self, change: &Change) -> bool {
+        change.is_applicable(self.members())
+    }
+
+    fn sync_req_msg(&self) -> StableSetMsg {
+        StableSetMsg::SyncReq {
+            generation: self.generation(),
+        }
+    }
+
+    fn sync_snapshot_msg(&self) -> StableSetMsg {
+        StableSetMsg::SyncSnapshot {
+            base: self.membership.base().clone(),
+            certs: self.membership.log().to_vec(),
+        }
+    }
+
+    fn broadcast(&self, msg: StableSetMsg) -> Outgoing {
+        self.members()
+            .iter()
+            .filter(|member| member.id != self.node.id)
+            .map(|member| (*member, msg.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keypair(secret: u8) -> Keypair {
+        Keypair::from_secret([secret; 32])
+    }
+
+    fn node(secret: u8) -> NetworkNode {
+        NetworkNode {
+            id: keypair(secret).id(),
+            addr: ([10, 0, 0, secret], 12000).into(),
+        }
+    }
+
+    fn genesis() -> MembershipSnapshot {
+        MembershipSnapshot {
+            generation: 0,
+            members: (1..=4).map(node).collect(),
+        }
+    }
+
+    /// Node 9 joining the genesis set, committed by the nodes of the passed in secrets.
+    fn join_cert(signers: &[u8]) -> QuorumCert {
+        let change = Change::Join(node(9));
+        let bytes = signed_bytes(0, 0, &Ballot::Commit(change));
+        QuorumCert {
+            generation: 0,
+            round: 0,
+            change,
+            signatures: signers
+                .iter()
+                .map(|secret| (keypair(*secret).id(), keypair(*secret).sign(&bytes)))
+                .collect(),
+        }
+    }
+
+    fn joiner() -> StableSet {
+        StableSet::joining(keypair(9), node(9).addr, genesis().members)
+    }
+
+    fn snapshot(base: MembershipSnapshot, certs: Vec<QuorumCert>) -> StableSetMsg {
+        StableSetMsg::SyncSnapshot { base, certs }
+    }
+
+    #[test]
+    fn snapshots_verified_from_the_genesis_set_are_adopted() {
+        let mut joiner = joiner();
+        let _ = joiner.handle_msg(node(1), snapshot(genesis(), vec![join_cert(&[1, 2, 3])]));
+        assert_eq!(joiner.generation(), 1);
+        assert!(joiner.is_member(&node(9).id));
+    }
+
+    #[test]
+    fn snapshots_from_non_members_are_adopted_if_verified() {
+        let mut joiner = joiner();
+        let _ = joiner.handle_msg(node(7), snapshot(genesis(), vec![join_cert(&[1, 2])]));
+        assert_eq!(joiner.generation(), 0);
+        let _ = joiner.handle_msg(node(7), snapshot(genesis(), vec![join_cert(&[1, 2, 3])]));
+        assert_eq!(joiner.generation(), 1);
+    }
+
+    #[test]
+    fn snapshots_with_forged_certs_are_ignored() {
+        let mut joiner = joiner();
+        let short = snapshot(genesis(), vec![join_cert(&[1, 2])]);
+        let _ = joiner.handle_msg(node(1), short);
+        let mut tampered = join_cert(&[1, 2, 3]);
+        tampered.change = Change::Join(node(8));
+        let _ = joiner.handle_msg(node(1), snapshot(genesis(), vec![tampered]));
+        assert_eq!(joiner.generation(), 0);
+        assert!(!joiner.is_member(&node(8).id));
+    }
+
+    #[test]
+    fn snapshots_from_an_unknown_set_are_ignored() {
+        let mut joiner = joiner();
+        // made up of the sender and its accomplices, who can certify whatever they like
+        let forged = MembershipSnapshot {
+            generation: 5,
+            members: [1, 7, 8].into_iter().map(node).collect(),
+        };
+        let _ = joiner.handle_msg(node(1), snapshot(forged, vec![]));
+        let mut other_genesis = genesis();
+        let _ = other_genesis.members.insert(node(7));
+        let _ = joiner.handle_msg(node(1), snapshot(other_genesis, vec![join_cert(&[1, 7])]));
+        assert_eq!(joiner.generation(), 0);
+    }
+
+    /// The ballots cast in the msgs, at `generation`.
+    fn witnessed(msgs: &Outgoing, generation: u64) -> BTreeSet<Ballot> {
+        msgs.iter()
+            .filter_map(|(_, msg)| match msg {
+                StableSetMsg::Witness(witness) if witness.generation == generation => {
+                    Some(witness.ballot)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Has nodes 2 and 3 witness and commit to the change in the first round,
+    /// which decides it along with our own ballots.
+    fn decide(set: &mut StableSet, change: Change) -> Outgoing {
+        let mut msgs = vec![];
+        for secret in [2, 3] {
+            for ballot in [Ballot::Change(change), Ballot::Commit(change)] {
+                let witness = Witness::new(&keypair(secret), 0, 0, ballot);
+                msgs.extend(set.handle_msg(node(secret), StableSetMsg::Witness(witness)));
+            }
+        }
+        msgs
+    }
+
+    #[test]
+    fn withdrawn_changes_are_not_pushed_for_in_later_generations() {
+        let join = Change::Join(node(9));
+        let evict = Change::Leave(node(4).id);
+        let mut set = StableSet::new(keypair(1), node(1).addr, genesis().members);
+        let msgs = set.propose(join);
+        assert_eq!(witnessed(&msgs, 0), BTreeSet::from([Ballot::Change(join)]));
+        // we already witnessed a change in this round
+        assert!(set.propose(evict).is_empty());
+        set.withdraw(&evict);
+
+        let msgs = decide(&mut set, join);
+        assert_eq!(set.generation(), 1);
+        assert!(witnessed(&msgs, 1).is_empty());
+    }
+
+    #[test]
+    fn pending_changes_are_pushed_for_in_later_generations() {
+        let join = Change::Join(node(9));
+        let evict = Change::Leave(node(4).id);
+        let mut set = StableSet::new(keypair(1), node(1).addr, genesis().members);
+        let _ = set.propose(join);
+        let _ = set.propose(evict);
+
+        let msgs = decide(&mut set, join);
+        assert_eq!(set.generation(), 1);
+        assert_eq!(witnessed(&msgs, 1), BTreeSet::from([Ballot::Change(evict)]));
+    }
+
+    #[test]
+    fn ballots_are_only_taken_up_to_the_next_round() {
+        let join = Change::Join(node(9));
+        let commit = |set: &mut StableSet, round| {
+            for secret in [2, 3, 4] {
+                let witness = Witness::new(&keypair(secret), 0, round, Ballot::Commit(join));
+                let _ = set.handle_msg(node(secret), StableSetMsg::Witness(witness));
+            }
+        };
+        let mut set = StableSet::new(keypair(1), node(1).addr, genesis().members);
+        commit(&mut set, 2);
+        assert_eq!(set.generation(), 0);
+        commit(&mut set, 1);
+        assert_eq!(set.generation(), 1);
+        assert_eq!(set.membership().log()[0].round, 1);
+    }
+
+    #[test]
+    fn non_members_keep_asking_the_members_to_sync() {
+        let msgs = joiner().tick();
+        let asked: BTreeSet<_> = msgs
+            .iter()
+            .filter(|(_, msg)| *msg == StableSetMsg::SyncReq { generation: 0 })
+            .map(|(node, _)| *node)
+            .collect();
+        assert_eq!(asked, genesis().members);
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::BTreeSet;
+use crate::comms::{MsgTrait, NetworkNode, NodeId};
 
-use crate::comms::{Comm, CommEvent, Error, MsgId, MsgTrait, NetworkMsg, NetworkNode};
+use super::{MembershipSnapshot, QuorumCert, Witness};
 
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +9,30 @@ pub enum StableSetMsg {
     #[default]
     Ping,
     Pong,
+    /// Asks the members to let the node join.
+    ReqJoin(NetworkNode),
+    /// Asks the members to let the node, which must be the sender, leave.
+    ReqLeave(NodeId),
+    /// The sender witnessed a change to the set.
+    Witness(Witness),
+    /// Asks for the changes applied after the given generation,
+    /// also telling the recipient which generation the sender is at.
+    SyncReq {
+        generation: u64,
+    },
+    /// Certificates of the changes applied after `generation`, for lagging nodes to catch up.
+    Sync {
+        generation: u64,
+        certs: Vec<QuorumCert>,
+    },
+    /// The whole history of the sender: the membership it started off from,
+    /// and the certificates of the changes applied since, which the recipient verifies
+    /// from that membership before adopting the result.
+    /// For nodes which have no history in common with the sender.
+    SyncSnapshot {
+        base: MembershipSnapshot,
+        certs: Vec<QuorumCert>,
+    },
 }
 
 impl MsgTrait for StableSetMsg {}
@@ -0,0 +1,89 @@
+mod sim;
+
+use sim::{Fault, NetConfig, Sim};
+
+use tokio::time::{Duration, Instant};
+
+/// The longest the honest nodes may take to let the joiners in,
+/// checking the safety invariants along the way.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the members are left to replay msgs to each other.
+const REPLAY_DURATION: Duration = Duration::from_secs(30);
+
+/// How often the addresses of the members are checked, in virtual time.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs honest nodes alongside faulty members, with joiners for them to decide on,
+/// checking that the honest nodes never disagree and still let the joiners in.
+fn honest_nodes_agree(honest: usize, faults: &[Fault]) {
+    for seed in sim::seeds(0..5) {
+        sim::run(async move {
+            let mut sim = Sim::with_faults(seed, honest, faults, NetConfig::lossy());
+            let joiners = [sim.add_node(), sim.add_node()].map(|index| sim.nodes[index].node);
+            let snapshot = sim
+                .run_checked_until(JOIN_TIMEOUT, |s| {
+                    joiners.iter().all(|joiner| s.members.contains(joiner))
+                })
+                .await;
+            assert!(
+                snapshot.is_some(),
+                "seed {seed}: honest nodes did not converge on letting the joiners in with {faults:?}"
+            );
+            sim.shutdown().await;
+        })
+    }
+}
+
+#[test]
+fn equivocating_member_cannot_split_the_set() {
+    honest_nodes_agree(3, &[Fault::Equivocate]);
+}
+
+#[test]
+fn conflicting_witnesses_cannot_split_the_set() {
+    honest_nodes_agree(3, &[Fault::ConflictingWitnesses]);
+}
+
+#[test]
+fn replayed_msgs_cannot_split_the_set() {
+    honest_nodes_agree(3, &[Fault::Replay]);
+}
+
+#[test]
+fn replayed_msgs_cannot_move_members() {
+    for seed in sim::seeds(0..5) {
+        sim::run(async move {
+            let sim = Sim::with_faults(seed, 3, &[Fault::Replay], NetConfig::lossy());
+            let honest = sim.members();
+            let deadline = Instant::now() + REPLAY_DURATION;
+            while Instant::now() < deadline {
+                sim.run_for(CHECK_INTERVAL).await;
+                for snapshot in sim.snapshots().await {
+                    assert!(
+                        honest.is_subset(&snapshot.members),
+                        "seed {seed}: honest members moved to the replaying member's address: {:?}",
+                        snapshot.members
+                    );
+                }
+            }
+            sim.shutdown().await;
+        })
+    }
+}
+
+#[test]
+fn silent_member_cannot_stall_the_set() {
+    honest_nodes_agree(3, &[Fault::Silent]);
+}
+
+#[test]
+fn forged_histories_cannot_mislead_members_or_joiners() {
+    honest_nodes_agree(3, &[Fault::ForgeSync]);
+}
+
+#[test]
+fn set_tolerates_just_under_a_third_of_faulty_members() {
+    honest_nodes_agree(5, &[Fault::Equivocate, Fault::ConflictingWitnesses]);
+    honest_nodes_agree(5, &[Fault::Replay, Fault::Silent]);
+}
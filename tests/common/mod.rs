@@ -0,0 +1,45 @@
+//! Fixtures shared by the comms tests.
+
+// each test binary uses a different part of the fixtures
+#![allow(dead_code)]
+
+use stableset_net::{
+    comms::{
+        wire::Handshake, Comm, CommConfig, CommEvent, Frame, Keypair, MemoryNetwork,
+        PROTOCOL_VERSION,
+    },
+    stableset::StableSetMsg,
+};
+
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::mpsc::Receiver;
+
+/// The address of the host on the test network.
+pub fn addr(host: u8) -> SocketAddr {
+    ([10, 0, 0, host], 12000).into()
+}
+
+/// A comm of a new keypair, bound to the address of the host.
+pub fn comm(network: &MemoryNetwork, host: u8) -> (Comm, Receiver<CommEvent<StableSetMsg>>) {
+    comm_with(network, Keypair::random(), host, CommConfig::default())
+}
+
+/// A comm of the keypair, bound to the address of the host, and configured as given.
+pub fn comm_with(
+    network: &MemoryNetwork,
+    keypair: Keypair,
+    host: u8,
+    config: CommConfig,
+) -> (Comm, Receiver<CommEvent<StableSetMsg>>) {
+    let (transport, incoming) = network.bind(addr(host)).expect("Failed to bind");
+    Comm::with_config(keypair, Arc::new(transport), incoming, config)
+}
+
+/// A handshake frame accepting our protocol version.
+pub fn acceptance() -> Frame {
+    Handshake::Accept {
+        version: PROTOCOL_VERSION,
+    }
+    .to_frame(Keypair::random().id(), None)
+    .expect("Failed to encode handshake")
+}
@@ -0,0 +1,85 @@
+mod sim;
+
+use sim::{NetConfig, Sim};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::time::Duration;
+
+const PARTITION_TIME: Duration = Duration::from_secs(20);
+/// The longest the nodes may take to converge once healed.
+const HEAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[test]
+fn random_partitions_never_split_the_membership() {
+    for seed in sim::seeds(0..10) {
+        sim::run(async move {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut sim = Sim::new(seed, rng.gen_range(4..=7), NetConfig::lossy());
+            for _ in 0..rng.gen_range(0..=2) {
+                let _ = sim.add_node();
+            }
+            sim.run_checked(Duration::from_secs(rng.gen_range(0..5)))
+                .await;
+
+            // spread the nodes over 2 or 3 sides, which make whatever progress they can
+            let sides = rng.gen_range(2..=3);
+            let mut groups = vec![vec![]; sides];
+            for index in 0..sim.nodes.len() {
+                groups[rng.gen_range(0..sides)].push(index);
+            }
+            let groups: Vec<&[usize]> = groups.iter().map(|group| group.as_slice()).collect();
+            sim.partition(&groups);
+            sim.run_checked(PARTITION_TIME).await;
+
+            sim.heal();
+            let converged = sim.run_checked_until(HEAL_TIMEOUT, |_| true).await;
+            assert!(
+                converged.is_some(),
+                "seed {seed} did not converge after healing"
+            );
+            sim.shutdown().await;
+        })
+    }
+}
+
+#[test]
+fn only_the_side_with_a_supermajority_makes_progress() {
+    for seed in sim::seeds(0..5) {
+        sim::run(async move {
+            let mut sim = Sim::new(seed, 5, NetConfig::reliable());
+            sim.partition(&[&[0, 1, 2, 3], &[4]]);
+            sim.run_checked(PARTITION_TIME).await;
+
+            let snapshots = sim.snapshots().await;
+            assert!(snapshots[..4].iter().all(|s| s.generation == 1));
+            assert_eq!(snapshots[4].generation, 0);
+
+            sim.heal();
+            let isolated = sim.nodes[4].node;
+            let converged = sim
+                .run_checked_until(HEAL_TIMEOUT, |s| !s.members.contains(&isolated))
+                .await;
+            assert!(converged.is_some(), "did not converge");
+            sim.shutdown().await;
+        })
+    }
+}
+
+#[test]
+fn evenly_split_set_decides_nothing() {
+    for seed in sim::seeds(0..5) {
+        sim::run(async move {
+            let mut sim = Sim::new(seed, 6, NetConfig::lossy());
+            sim.partition(&[&[0, 1, 2], &[3, 4, 5]]);
+            sim.run_checked(PARTITION_TIME).await;
+
+            let snapshots = sim.snapshots().await;
+            assert!(snapshots.iter().all(|s| s.generation == 0));
+
+            sim.heal();
+            let converged = sim.run_checked_until(HEAL_TIMEOUT, |_| true).await;
+            assert!(converged.is_some(), "did not converge");
+            sim.shutdown().await;
+        })
+    }
+}
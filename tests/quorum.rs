@@ -0,0 +1,237 @@
+mod common;
+
+use common::comm;
+
+use stableset_net::{
+    comms::{
+        CommEvent, Error, ErrorCode, ErrorResponse, MemoryNetwork, MsgId, MsgReceived, NetworkNode,
+        NodeId, Quorum, QuorumResponse, Result, SendOptions,
+    },
+    stableset::StableSetMsg,
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::time::{Duration, Instant};
+
+fn value(generation: u64) -> StableSetMsg {
+    StableSetMsg::SyncReq { generation }
+}
+
+/// How a node handles the msgs fanned out to it.
+#[derive(Clone)]
+enum Node {
+    Responds(StableSetMsg),
+    /// Drops the msgs without responding.
+    Fails,
+    /// Holds on to the msgs, never responding.
+    Stalls,
+}
+
+use Node::*;
+
+/// Has a client ask a node to fan its msg out to the passed in nodes, and returns what the client
+/// gets back, along with the ids of the nodes, in the order they were passed in.
+async fn fan_out(
+    quorum: Quorum,
+    nodes: &[Node],
+) -> (Result<QuorumResponse<StableSetMsg>>, Vec<NodeId>) {
+    fan_out_with(quorum, nodes, SendOptions::default(), false).await
+}
+
+/// Fans out as `fan_out` does, the fan-out being sent with the options, and cancelled at once if so told.
+async fn fan_out_with(
+    quorum: Quorum,
+    behaviours: &[Node],
+    options: SendOptions,
+    cancel: bool,
+) -> (Result<QuorumResponse<StableSetMsg>>, Vec<NodeId>) {
+    let network = MemoryNetwork::new();
+    let (client, _events) = comm(&network, 1);
+    let (aggregator, mut aggregator_events) = comm(&network, 2);
+
+    let mut nodes = vec![];
+    for (host, behaviour) in (3..).zip(behaviours.iter().cloned()) {
+        let (node, mut events) = comm(&network, host);
+        nodes.push(node.our_node());
+        let _handle = tokio::spawn(async move {
+            let _node = node;
+            let mut stalled = vec![];
+            while let Some(event) = events.recv().await {
+                if let CommEvent::Msg(MsgReceived {
+                    responder: Some(responder),
+                    ..
+                }) = event
+                {
+                    match behaviour.clone() {
+                        Responds(payload) => {
+                            let _ = responder.respond(payload).await;
+                        }
+                        Fails => drop(responder),
+                        Stalls => stalled.push(responder),
+                    }
+                }
+            }
+        });
+    }
+
+    let targets: BTreeSet<NetworkNode> = nodes.iter().copied().collect();
+    let node_ids = nodes.iter().map(|node| node.id).collect();
+    let aggregator_node = aggregator.our_node();
+    let _handle = tokio::spawn(async move {
+        aggregator.set_comm_targets(targets.clone());
+        while let Some(event) = aggregator_events.recv().await {
+            let responder = match event {
+                CommEvent::Msg(MsgReceived {
+                    responder: Some(responder),
+                    ..
+                }) => responder,
+                _ => continue,
+            };
+            let msg_id = MsgId::new();
+            let node_frames: BTreeMap<_, _> = targets
+                .iter()
+                .map(|node| {
+                    let frame = aggregator
+                        .signed_msg(msg_id, node.id, StableSetMsg::Ping)
+                        .and_then(|msg| msg.to_frame())
+                        .expect("Failed to encode msg");
+                    (*node, frame)
+                })
+                .collect();
+            let expected_targets = node_frames.len();
+            let fan_out = aggregator.send_and_respond_on_stream(
+                msg_id,
+                node_frames,
+                expected_targets,
+                quorum,
+                responder,
+                options,
+            );
+            if cancel {
+                drop(fan_out)
+            } else {
+                fan_out.detach()
+            }
+        }
+    });
+
+    let response = client
+        .request(aggregator_node, StableSetMsg::Ping, SendOptions::default())
+        .await;
+    (response, node_ids)
+}
+
+fn error_response(response: Result<QuorumResponse<StableSetMsg>>) -> ErrorResponse {
+    match response {
+        Err(Error::ErrorResponse(error)) => error,
+        other => panic!("Expected an error response, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn all_equal_needs_every_node_to_agree() {
+    let (response, _) = fan_out(Quorum::AllEqual, &[Responds(value(1)), Responds(value(1))]).await;
+    let response = response.expect("Request failed");
+    assert_eq!(response.value, value(1));
+    assert!(response.disagreed.is_empty());
+
+    // a tie, either node being the one which disagreed
+    let (response, nodes) =
+        fan_out(Quorum::AllEqual, &[Responds(value(1)), Responds(value(2))]).await;
+    let error = error_response(response);
+    assert_eq!(error.code, ErrorCode::QuorumDisagreed);
+    assert_eq!(error.failed_nodes.len(), 1);
+    assert!(error.failed_nodes.iter().all(|node| nodes.contains(node)));
+
+    let (response, nodes) = fan_out(Quorum::AllEqual, &[Responds(value(1)), Fails]).await;
+    let error = error_response(response);
+    assert_eq!(error.code, ErrorCode::NodeUnreachable);
+    assert_eq!(error.failed_nodes, BTreeSet::from([nodes[1]]));
+}
+
+#[tokio::test]
+async fn majority_reports_the_nodes_which_disagreed() {
+    let payloads = [Responds(value(1)), Responds(value(2)), Responds(value(1))];
+    let (response, nodes) = fan_out(Quorum::Majority, &payloads).await;
+    let response = response.expect("Request failed");
+    assert_eq!(response.value, value(1));
+    assert_eq!(response.disagreed, BTreeSet::from([nodes[1]]));
+
+    // half of the nodes is no majority
+    let payloads = [
+        Responds(value(1)),
+        Responds(value(2)),
+        Responds(value(1)),
+        Fails,
+    ];
+    let (response, nodes) = fan_out(Quorum::Majority, &payloads).await;
+    let error = error_response(response);
+    assert_eq!(error.code, ErrorCode::QuorumDisagreed);
+    assert_eq!(error.failed_nodes, BTreeSet::from([nodes[1], nodes[3]]));
+}
+
+#[tokio::test]
+async fn threshold_needs_as_many_nodes_to_agree() {
+    let payloads = [
+        Responds(value(1)),
+        Responds(value(2)),
+        Responds(value(1)),
+        Fails,
+    ];
+    let (response, nodes) = fan_out(Quorum::Threshold(2), &payloads).await;
+    let response = response.expect("Request failed");
+    assert_eq!(response.value, value(1));
+    assert_eq!(response.disagreed, BTreeSet::from([nodes[1]]));
+
+    let (response, _) = fan_out(Quorum::Threshold(3), &payloads).await;
+    assert_eq!(error_response(response).code, ErrorCode::QuorumDisagreed);
+}
+
+#[tokio::test]
+async fn first_success_takes_any_response() {
+    let (response, _) = fan_out(Quorum::FirstSuccess, &[Fails, Responds(value(2)), Fails]).await;
+    let response = response.expect("Request failed");
+    assert_eq!(response.value, value(2));
+    assert!(response.disagreed.is_empty());
+
+    let (response, nodes) = fan_out(Quorum::FirstSuccess, &[Fails, Fails]).await;
+    let error = error_response(response);
+    assert_eq!(error.code, ErrorCode::NodeUnreachable);
+    assert_eq!(error.failed_nodes, nodes.into_iter().collect());
+}
+
+#[tokio::test(start_paused = true)]
+async fn nodes_yet_to_respond_at_the_deadline_are_unreachable() {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let nodes = [Responds(value(1)), Stalls, Responds(value(1))];
+
+    let options = SendOptions::with_deadline(deadline);
+    let (response, _) = fan_out_with(Quorum::Majority, &nodes, options, false).await;
+    let response = response.expect("Request failed");
+    assert_eq!(response.value, value(1));
+    assert!(response.disagreed.is_empty());
+    assert!(Instant::now() >= deadline);
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let options = SendOptions::with_deadline(deadline);
+    let (response, node_ids) = fan_out_with(Quorum::AllEqual, &nodes, options, false).await;
+    let error = error_response(response);
+    assert_eq!(error.code, ErrorCode::NodeUnreachable);
+    assert_eq!(error.failed_nodes, BTreeSet::from([node_ids[1]]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn dropping_the_handle_cancels_the_fan_out() {
+    let started = Instant::now();
+    let (response, _) = fan_out_with(
+        Quorum::Majority,
+        &[Stalls, Stalls],
+        SendOptions::default(),
+        true,
+    )
+    .await;
+    let error = error_response(response);
+    assert_eq!(error.code, ErrorCode::Internal);
+    assert!(error.reason.contains("cancelled"), "{}", error.reason);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
@@ -0,0 +1,249 @@
+mod common;
+
+use common::{acceptance, comm};
+
+use stableset_net::{
+    comms::{
+        wire::{self, Handshake},
+        Comm, CommEvent, Error, Keypair, MemoryNetwork, MsgId, NetworkMsg, NetworkNode,
+        RetryPolicy, SendOptions, Transport, DEFAULT_MAX_MSG_SIZE,
+    },
+    stableset::StableSetMsg,
+};
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+fn send_reliably(sender: &Comm, node: NetworkNode, options: SendOptions) -> MsgId {
+    let msg = sender
+        .signed_reliable_msg(MsgId::new(), node.id, StableSetMsg::Ping)
+        .expect("Failed to sign msg");
+    let frame = msg.to_frame().expect("Failed to encode msg");
+    sender.send_out_frame(node, msg.id, frame, options);
+    msg.id
+}
+
+/// Waits for the sender to have no msg left to be acked.
+async fn all_acked(sender: &Comm) {
+    timeout(Duration::from_secs(5), async {
+        while !sender.unacked().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The msgs were not acked");
+}
+
+#[tokio::test]
+async fn reliable_msgs_are_acked() {
+    let network = MemoryNetwork::new();
+    let (sender, _sender_events) = comm(&network, 1);
+    let (receiver, mut events) = comm(&network, 2);
+
+    let msg_id = send_reliably(&sender, receiver.our_node(), SendOptions::reliable());
+    match events.recv().await {
+        Some(CommEvent::Msg(received)) => {
+            assert_eq!(received.wire_msg.id, msg_id);
+            assert_eq!(received.sender.id, sender.our_node().id);
+            assert!(received.responder.is_none());
+        }
+        other => panic!("Expected the msg, got {other:?}"),
+    }
+    all_acked(&sender).await;
+}
+
+#[tokio::test]
+async fn copies_of_a_reliable_msg_are_acked_but_handed_on_once() {
+    let network = MemoryNetwork::new();
+    let (receiver, mut events) = comm(&network, 1);
+    let receiver_node = receiver.our_node();
+
+    let (sender, _incoming) = network
+        .bind(common::addr(2))
+        .expect("Failed to bind sender");
+    let connection = sender
+        .connect(receiver_node.addr)
+        .await
+        .expect("Failed to connect");
+    let msg = NetworkMsg::signed_reliable(
+        &Keypair::random(),
+        MsgId::new(),
+        receiver_node.id,
+        StableSetMsg::Ping,
+    )
+    .expect("Failed to sign msg");
+    let frame = msg.to_frame().expect("Failed to encode msg");
+
+    for _ in 0..3 {
+        let ack = connection
+            .request(frame.clone())
+            .await
+            .expect("The msg was not acked");
+        // the same msg, acked by the receiver
+        assert!(wire::is_ack(&ack, msg.id, receiver_node.id));
+    }
+
+    match events.recv().await {
+        Some(CommEvent::Msg(received)) => assert_eq!(received.wire_msg.id, msg.id),
+        other => panic!("Expected the msg, got {other:?}"),
+    }
+    assert!(timeout(Duration::from_secs(1), events.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn reliable_msgs_are_sent_again_until_acked() {
+    let network = MemoryNetwork::new();
+    let (sender, _sender_events) = comm(&network, 1);
+
+    // a node which drops the first two copies of the msg, and acks the third
+    let keypair = Keypair::random();
+    let addr = common::addr(2);
+    let (_transport, mut incoming) = network.bind(addr).expect("Failed to bind peer");
+    let node = NetworkNode {
+        id: keypair.id(),
+        addr,
+    };
+    let copies = Arc::new(AtomicUsize::new(0));
+    let received = copies.clone();
+    let _handle = tokio::spawn(async move {
+        while let Some(mut conn) = incoming.recv().await {
+            let received = received.clone();
+            let keypair = keypair.clone();
+            let _handle = tokio::spawn(async move {
+                while let Some(Ok(msg)) = conn.msgs.recv().await {
+                    let stream = match msg.send_stream {
+                        Some(stream) => stream,
+                        None => continue,
+                    };
+                    if Handshake::from_frame(&msg.frame).is_ok() {
+                        let _ = stream.send(acceptance()).await;
+                    } else if received.fetch_add(1, Ordering::SeqCst) == 2 {
+                        let msg =
+                            NetworkMsg::<StableSetMsg>::from_frame(msg.frame, DEFAULT_MAX_MSG_SIZE)
+                                .expect("Failed to decode msg");
+                        let _ = stream.send(wire::ack(msg.id, keypair.id(), msg.src)).await;
+                    }
+                }
+            });
+        }
+    });
+
+    let started = Instant::now();
+    let options = SendOptions {
+        retry: Some(RetryPolicy::no_retries()),
+        ..SendOptions::reliable()
+    };
+    let msg_id = send_reliably(&sender, node, options);
+    assert!(sender.unacked().contains(&msg_id));
+    all_acked(&sender).await;
+    assert_eq!(copies.load(Ordering::SeqCst), 3);
+    // a copy was sent each time the ack timeout was up
+    assert!(started.elapsed() >= Duration::from_secs(2));
+}
+
+#[tokio::test(start_paused = true)]
+async fn reliable_msgs_sent_again_while_unacked_go_out_once() {
+    let network = MemoryNetwork::new();
+    let (sender, _sender_events) = comm(&network, 1);
+
+    // a node which never acks, holding on to the streams to ack on
+    let addr = common::addr(2);
+    let (_transport, mut incoming) = network.bind(addr).expect("Failed to bind peer");
+    let node = NetworkNode {
+        id: Keypair::random().id(),
+        addr,
+    };
+    let copies = Arc::new(AtomicUsize::new(0));
+    let received = copies.clone();
+    let _handle = tokio::spawn(async move {
+        while let Some(mut conn) = incoming.recv().await {
+            let received = received.clone();
+            let _handle = tokio::spawn(async move {
+                let mut streams = vec![];
+                while let Some(Ok(msg)) = conn.msgs.recv().await {
+                    if Handshake::from_frame(&msg.frame).is_ok() {
+                        if let Some(stream) = msg.send_stream {
+                            let _ = stream.send(acceptance()).await;
+                        }
+                    } else {
+                        let _ = received.fetch_add(1, Ordering::SeqCst);
+                        streams.push(msg.send_stream);
+                    }
+                }
+            });
+        }
+    });
+
+    let msg = sender
+        .signed_reliable_msg(MsgId::new(), node.id, StableSetMsg::Ping)
+        .expect("Failed to sign msg");
+    let frame = msg.to_frame().expect("Failed to encode msg");
+    for _ in 0..3 {
+        sender.send_out_frame(node, msg.id, frame.clone(), SendOptions::reliable());
+    }
+    // short of the ack timeout, after which it is sent again
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(copies.load(Ordering::SeqCst), 1);
+    assert_eq!(sender.unacked().len(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reliable_msgs_unacked_by_their_deadline_are_reported() {
+    let network = MemoryNetwork::new();
+    let (sender, mut events) = comm(&network, 1);
+    let nobody = NetworkNode {
+        id: Keypair::random().id(),
+        addr: common::addr(9),
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(3);
+    let options = SendOptions {
+        deadline: Some(deadline),
+        ..SendOptions::reliable()
+    };
+    let msg_id = send_reliably(&sender, nobody, options);
+    match events.recv().await {
+        Some(CommEvent::Error { node_id, error }) => {
+            assert_eq!(node_id, nobody);
+            assert!(
+                matches!(error, Error::NotAcked(id) if id == msg_id),
+                "{error:?}"
+            );
+        }
+        other => panic!("Expected the msg not to be acked, got {other:?}"),
+    }
+    assert!(Instant::now() >= deadline);
+    assert!(sender.unacked().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn flushes_wait_for_reliable_msgs_to_be_acked_or_given_up_on() {
+    let network = MemoryNetwork::new();
+    let (sender, _sender_events) = comm(&network, 1);
+    let (receiver, _events) = comm(&network, 2);
+    let nobody = NetworkNode {
+        id: Keypair::random().id(),
+        addr: common::addr(9),
+    };
+
+    let _ = send_reliably(&sender, receiver.our_node(), SendOptions::reliable());
+    let deadline = Instant::now() + Duration::from_secs(3);
+    let _ = send_reliably(&sender, nobody, SendOptions::with_deadline(deadline));
+    let _ = send_reliably(
+        &sender,
+        nobody,
+        SendOptions {
+            deadline: Some(deadline),
+            ..SendOptions::reliable()
+        },
+    );
+
+    sender.flush().await;
+    assert!(sender.unacked().is_empty());
+    assert!(Instant::now() >= deadline);
+}
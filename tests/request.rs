@@ -0,0 +1,279 @@
+mod common;
+
+use common::{acceptance, comm};
+
+use stableset_net::{
+    comms::{
+        CommEvent, Error, ErrorCode, Keypair, MemoryNetwork, MsgId, MsgReceived, NetworkMsg,
+        NetworkNode, Responder, SendOptions, DEFAULT_MAX_MSG_SIZE,
+    },
+    stableset::StableSetMsg,
+};
+
+use std::{collections::BTreeSet, future::Future};
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+/// Answers each request with what `respond` makes of it.
+fn respond_with<F>(
+    mut events: Receiver<CommEvent<StableSetMsg>>,
+    respond: fn(Responder<StableSetMsg>) -> F,
+) where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let _handle = tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let CommEvent::Msg(MsgReceived {
+                responder: Some(responder),
+                ..
+            }) = event
+            {
+                respond(responder).await;
+            }
+        }
+    });
+}
+
+#[tokio::test]
+async fn request_returns_the_response() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let (responder, events) = comm(&network, 2);
+    let responder_node = responder.our_node();
+
+    respond_with(events, |responder| async move {
+        let _ = responder.respond(StableSetMsg::Pong).await;
+    });
+
+    let response: StableSetMsg = requester
+        .request(responder_node, StableSetMsg::Ping, SendOptions::default())
+        .await
+        .expect("Request failed");
+    assert_eq!(response, StableSetMsg::Pong);
+
+    // requests are independent of one another
+    let responses = futures::future::join_all((0..10).map(|_| {
+        requester.request::<_, StableSetMsg>(
+            responder_node,
+            StableSetMsg::Ping,
+            SendOptions::default(),
+        )
+    }))
+    .await;
+    assert!(responses
+        .into_iter()
+        .all(|response| matches!(response, Ok(StableSetMsg::Pong))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn request_times_out_without_a_response() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let (responder, events) = comm(&network, 2);
+    let responder_node = responder.our_node();
+    let _responder = responder;
+
+    // keeps the requests open, without ever responding
+    let mut events = events;
+    let _handle = tokio::spawn(async move {
+        let mut responders = vec![];
+        while let Some(CommEvent::Msg(msg)) = events.recv().await {
+            responders.push(msg.responder);
+        }
+    });
+
+    let result = requester
+        .request::<_, StableSetMsg>(responder_node, StableSetMsg::Ping, SendOptions::default())
+        .await;
+    assert!(
+        matches!(result, Err(Error::RequestTimeout(_))),
+        "{result:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_are_given_up_on_at_the_deadline_of_their_options() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let (responder, mut events) = comm(&network, 2);
+    let responder_node = responder.our_node();
+    let _responder = responder;
+
+    // keeps the requests open, without ever responding
+    let _handle = tokio::spawn(async move {
+        let mut responders = vec![];
+        while let Some(CommEvent::Msg(msg)) = events.recv().await {
+            responders.push(msg.responder);
+        }
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(3);
+    let result = requester
+        .request::<_, StableSetMsg>(
+            responder_node,
+            StableSetMsg::Ping,
+            SendOptions::with_deadline(deadline),
+        )
+        .await;
+    assert!(
+        matches!(result, Err(Error::RequestTimeout(_))),
+        "{result:?}"
+    );
+    // well before the configured request timeout
+    assert!(Instant::now() >= deadline);
+    assert!(Instant::now() < deadline + Duration::from_secs(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn responses_are_given_up_on_at_their_deadline() {
+    let network = MemoryNetwork::new();
+    let (requester, mut requester_events) = comm(&network, 1);
+    let (responder, mut events) = comm(&network, 2);
+    let responder_node = responder.our_node();
+    let _responder = responder;
+
+    // keeps the requests open, without ever responding
+    let _handle = tokio::spawn(async move {
+        let mut responders = vec![];
+        while let Some(CommEvent::Msg(msg)) = events.recv().await {
+            responders.push(msg.responder);
+        }
+    });
+
+    requester.set_comm_targets(BTreeSet::from([responder_node]));
+    let msg = requester
+        .signed_msg(MsgId::new(), responder_node.id, StableSetMsg::Ping)
+        .expect("Failed to sign msg");
+    let deadline = Instant::now() + Duration::from_secs(3);
+    requester.send_and_return_response(
+        responder_node,
+        msg.id,
+        msg.to_frame().expect("Failed to encode msg"),
+        SendOptions::with_deadline(deadline),
+    );
+
+    match requester_events.recv().await {
+        Some(CommEvent::Error { node_id, error }) => {
+            assert_eq!(node_id, responder_node);
+            assert!(
+                matches!(error, Error::RequestTimeout(id) if id == msg.id),
+                "{error:?}"
+            );
+        }
+        other => panic!("Expected the request to time out, got {other:?}"),
+    }
+    assert!(Instant::now() >= deadline);
+}
+
+#[tokio::test]
+async fn errors_are_responded_with_their_code_and_reason() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let (responder, events) = comm(&network, 2);
+    let responder_node = responder.our_node();
+
+    respond_with(events, |responder| async move {
+        let error = Error::InvalidMsgReceived(responder.msg_id());
+        let _ = responder.respond_error(error).await;
+    });
+
+    let result = requester
+        .request::<_, StableSetMsg>(responder_node, StableSetMsg::Pong, SendOptions::default())
+        .await;
+    let error = match result {
+        Err(Error::ErrorResponse(error)) => error,
+        other => panic!("Expected an error response, got {other:?}"),
+    };
+    assert_eq!(error.code, ErrorCode::InvalidRequest);
+    assert!(error.reason.contains("invalid"), "{}", error.reason);
+    assert!(error.failed_nodes.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn dropped_responders_fail_the_request_at_once() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let (responder, events) = comm(&network, 2);
+    let responder_node = responder.our_node();
+
+    respond_with(events, |responder| async move { drop(responder) });
+
+    let started = Instant::now();
+    let result = requester
+        .request::<_, StableSetMsg>(responder_node, StableSetMsg::Ping, SendOptions::default())
+        .await;
+    assert!(matches!(result, Err(Error::FailedSend(_))), "{result:?}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn request_refuses_responses_to_other_requests() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+
+    // a node which answers the handshake, and then each msg with a msg of another id
+    let keypair = Keypair::random();
+    let addr = common::addr(2);
+    let (_transport, mut incoming) = network.bind(addr).expect("Failed to bind peer");
+    let peer = NetworkNode {
+        id: keypair.id(),
+        addr,
+    };
+    let _handle = tokio::spawn(async move {
+        while let Some(mut conn) = incoming.recv().await {
+            let keypair = keypair.clone();
+            let _handle = tokio::spawn(async move {
+                while let Some(Ok(msg)) = conn.msgs.recv().await {
+                    let stream = match msg.send_stream {
+                        Some(stream) => stream,
+                        None => continue,
+                    };
+                    let response = match NetworkMsg::<StableSetMsg>::from_frame(
+                        msg.frame,
+                        DEFAULT_MAX_MSG_SIZE,
+                    ) {
+                        Ok(request) => NetworkMsg::signed(
+                            &keypair,
+                            MsgId::new(),
+                            request.src,
+                            StableSetMsg::Pong,
+                        )
+                        .and_then(|response| response.to_frame())
+                        .expect("Failed to encode response"),
+                        Err(_) => acceptance(),
+                    };
+                    let _ = stream.send(response).await;
+                }
+            });
+        }
+    });
+
+    let result = requester
+        .request::<_, StableSetMsg>(peer, StableSetMsg::Ping, SendOptions::default())
+        .await;
+    assert!(
+        matches!(result, Err(Error::InvalidMsgReceived(_))),
+        "{result:?}"
+    );
+}
+
+#[tokio::test]
+async fn request_to_an_unreachable_node_fails() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let nobody = NetworkNode {
+        id: Keypair::random().id(),
+        addr: common::addr(9),
+    };
+
+    let result = requester
+        .request::<_, StableSetMsg>(nobody, StableSetMsg::Ping, SendOptions::default())
+        .await;
+    assert!(result.is_err());
+    assert!(
+        !matches!(result, Err(Error::RequestTimeout(_))),
+        "{result:?}"
+    );
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use common::{comm, comm_with};
+
+use stableset_net::{
+    comms::{
+        Comm, CommConfig, CommEvent, Error, Keypair, MemoryNetwork, MsgId, MsgReceived,
+        NetworkNode, RetryPolicy, RetryStats, SendOptions,
+    },
+    stableset::StableSetMsg,
+};
+
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{sleep, timeout, Duration, Instant},
+};
+
+/// A node nothing listens for yet.
+fn absent_node(keypair: &Keypair) -> NetworkNode {
+    NetworkNode {
+        id: keypair.id(),
+        addr: common::addr(9),
+    }
+}
+
+/// Sends a msg to the node with the options, and returns the error it failed with.
+async fn failed_send(
+    sender: &Comm,
+    events: &mut Receiver<CommEvent<StableSetMsg>>,
+    node: NetworkNode,
+    options: SendOptions,
+) -> Error {
+    let msg = sender
+        .signed_msg(MsgId::new(), node.id, StableSetMsg::Ping)
+        .expect("Failed to sign msg");
+    let frame = msg.to_frame().expect("Failed to encode msg");
+    sender.send_out_frame(node, msg.id, frame, options);
+    match events.recv().await {
+        Some(CommEvent::Error { node_id, error }) if node_id == node => error,
+        other => panic!("Expected the send to fail, got {other:?}"),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn sends_are_retried_as_the_policy_tells() {
+    let network = MemoryNetwork::new();
+    let (sender, mut events) = comm(&network, 1);
+    let node = absent_node(&Keypair::random());
+
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_secs(1),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let error = failed_send(&sender, &mut events, node, SendOptions::with_retry(policy)).await;
+    assert!(matches!(error, Error::FailedSend(_)), "{error:?}");
+    // backed off for at least half of 1s, and then of 2s
+    assert!(started.elapsed() >= Duration::from_millis(1500));
+    assert_eq!(
+        sender.retry_stats(),
+        RetryStats {
+            attempts: 3,
+            retries: 2,
+            recovered: 0,
+            exhausted: 1,
+        }
+    );
+
+    let error = failed_send(
+        &sender,
+        &mut events,
+        node,
+        SendOptions::with_retry(RetryPolicy::no_retries()),
+    )
+    .await;
+    assert!(matches!(error, Error::FailedSend(_)), "{error:?}");
+    let stats = sender.retry_stats();
+    assert_eq!((stats.attempts, stats.retries), (4, 2));
+}
+
+#[tokio::test(start_paused = true)]
+async fn retries_stop_once_the_max_elapsed_time_is_up() {
+    let network = MemoryNetwork::new();
+    let (sender, mut events) = comm(&network, 1);
+    let node = absent_node(&Keypair::random());
+
+    let policy = RetryPolicy {
+        max_attempts: 100,
+        initial_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(1),
+        max_elapsed: Some(Duration::from_secs(3)),
+        jitter: true,
+    };
+    let started = Instant::now();
+    let _ = failed_send(&sender, &mut events, node, SendOptions::with_retry(policy)).await;
+    assert!(started.elapsed() <= Duration::from_secs(3));
+    let stats = sender.retry_stats();
+    assert!((4..=7).contains(&stats.attempts), "{stats:?}");
+    assert_eq!(stats.exhausted, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_are_retried_until_the_node_is_up() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let keypair = Keypair::random();
+    let node = absent_node(&keypair);
+
+    // the node comes up after the first retries have failed
+    let _handle = tokio::spawn(async move {
+        sleep(Duration::from_millis(120)).await;
+        let (_responder, mut events) = comm_with(&network, keypair, 9, CommConfig::default());
+        while let Some(event) = events.recv().await {
+            if let CommEvent::Msg(MsgReceived {
+                responder: Some(responder),
+                ..
+            }) = event
+            {
+                let _ = responder.respond(StableSetMsg::Pong).await;
+            }
+        }
+    });
+
+    let response: StableSetMsg = requester
+        .request(node, StableSetMsg::Ping, SendOptions::default())
+        .await
+        .expect("Request failed");
+    assert_eq!(response, StableSetMsg::Pong);
+    let stats = requester.retry_stats();
+    assert!(stats.retries >= 2, "{stats:?}");
+    assert_eq!(stats.recovered, 1);
+    assert_eq!(stats.exhausted, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_are_retried_as_their_options_tell() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let node = absent_node(&Keypair::random());
+
+    let result = requester
+        .request::<_, StableSetMsg>(
+            node,
+            StableSetMsg::Ping,
+            SendOptions::with_retry(RetryPolicy::no_retries()),
+        )
+        .await;
+    assert!(result.is_err());
+    let stats = requester.retry_stats();
+    assert_eq!((stats.attempts, stats.retries, stats.exhausted), (1, 0, 1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_are_not_sent_again_once_sent() {
+    let network = MemoryNetwork::new();
+    let (requester, _events) = comm(&network, 1);
+    let (responder, mut events) = comm(&network, 2);
+    let node = responder.our_node();
+    let _responder = responder;
+
+    // the node gets the request, and drops it without a response
+    let received = tokio::spawn(async move {
+        let mut received = 0;
+        while let Ok(Some(CommEvent::Msg(_))) = timeout(Duration::from_secs(5), events.recv()).await
+        {
+            received += 1;
+        }
+        received
+    });
+
+    let result = requester
+        .request::<_, StableSetMsg>(node, StableSetMsg::Ping, SendOptions::default())
+        .await;
+    assert!(matches!(result, Err(Error::FailedSend(_))), "{result:?}");
+    assert_eq!(received.await.expect("The node failed"), 1);
+    let stats = requester.retry_stats();
+    assert_eq!(stats.retries, 0);
+    assert_eq!(stats.recovered, 0);
+}
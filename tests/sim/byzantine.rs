@@ -0,0 +1,413 @@
+//! Faulty members, to check the stable set holds up against them.
+//!
+//! An adversary runs the real stable set logic to keep track of the set,
+//! but lies about its votes or the history of the set, replays msgs or goes silent, as configured.
+
+use super::NetFaults;
+
+use stableset_net::{
+    comms::{
+        Comm, CommEvent, Keypair, MsgId, MsgReceived, NetworkMsg, NetworkNode, SendOptions,
+        DEFAULT_MAX_MSG_SIZE,
+    },
+    stableset::{
+        Ballot, Change, MembershipSnapshot, Outgoing, QuorumCert, StableSet, StableSetMsg, Witness,
+    },
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{interval, Duration, MissedTickBehavior},
+};
+
+/// How often the adversary re-sends its lies and replays msgs.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of received msgs kept around for replay.
+const REPLAY_BUFFER: usize = 1_000;
+
+/// Number of old msgs replayed on every tick.
+const REPLAYS_PER_TICK: usize = 5;
+
+/// How far ahead of the set the made up histories claim to be.
+const FORGED_GENERATIONS: u64 = 1_000;
+
+/// How a faulty member misbehaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Witnesses and commits to two different changes in every round, sending both to every member.
+    Equivocate,
+    /// Witnesses and commits to a different change for each half of the members.
+    ConflictingWitnesses,
+    /// Follows the protocol, but also sends old msgs again: other members' to random members,
+    /// and its own to their original recipients. It also eavesdrops on the network, to send
+    /// the msgs of other members again to their original recipients, from its own address.
+    Replay,
+    /// Stays connected but never sends anything, not even pongs.
+    Silent,
+    /// Follows the protocol, but also sends members and joiners made up histories of the set:
+    /// syncs and snapshots certified by itself alone, and snapshots of a set of its own making.
+    ForgeSync,
+}
+
+/// Handle to a running adversary.
+#[derive(Debug)]
+pub struct AdversaryHandle {
+    pub node: NetworkNode,
+    pub fault: Fault,
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl AdversaryHandle {
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.await;
+    }
+}
+
+/// A faulty member of the set.
+pub struct Adversary {
+    fault: Fault,
+    comm: Comm,
+    comm_events: mpsc::Receiver<CommEvent<StableSetMsg>>,
+    keypair: Keypair,
+    /// Keeps track of the set, its own votes are never sent out as is.
+    stable_set: StableSet,
+    /// The network, to eavesdrop on.
+    tap: Arc<NetFaults>,
+    rng: StdRng,
+    /// Msgs received from others, to replay.
+    received: Vec<NetworkMsg<StableSetMsg>>,
+    /// Msgs sent to others, to replay.
+    sent: Vec<(NetworkNode, NetworkMsg<StableSetMsg>)>,
+    /// The witnesses sent to each member, per generation and round we lied in.
+    lies: BTreeMap<(u64, u64), Outgoing>,
+}
+
+impl Adversary {
+    /// A genesis member, the genesis set being the passed in peers and itself.
+    pub fn new(
+        comm: Comm,
+        comm_events: mpsc::Receiver<CommEvent<StableSetMsg>>,
+        peers: BTreeSet<NetworkNode>,
+        fault: Fault,
+        tap: Arc<NetFaults>,
+        seed: u64,
+    ) -> Self {
+        let keypair = comm.keypair().clone();
+        let mut genesis = peers;
+        let _ = genesis.insert(comm.our_node());
+        let stable_set = StableSet::new(keypair.clone(), comm.socket_addr(), genesis);
+        Self {
+            fault,
+            comm,
+            comm_events,
+            keypair,
+            stable_set,
+            tap,
+            rng: StdRng::seed_from_u64(seed),
+            received: vec![],
+            sent: vec![],
+            lies: BTreeMap::new(),
+        }
+    }
+
+    pub fn start(self) -> AdversaryHandle {
+        let node = self.comm.our_node();
+        let fault = self.fault;
+        let (stop, stopped) = oneshot::channel();
+        let handle = tokio::spawn(self.run(stopped));
+        AdversaryHandle {
+            node,
+            fault,
+            stop,
+            handle,
+        }
+    }
+
+    async fn run(mut self, mut stopped: oneshot::Receiver<()>) {
+        let mut ticker = interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                biased;
+                _ = ticker.tick() => self.handle_tick(),
+                _ = &mut stopped => break,
+                event = self.comm_events.recv() => match event {
+                    Some(CommEvent::Msg(msg)) => self.handle_msg(msg),
+                    Some(CommEvent::Error { .. }) => {}
+                    None => break,
+                },
+            }
+        }
+        self.comm.close_endpoint();
+    }
+
+    fn handle_tick(&mut self) {
+        if self.fault == Fault::Silent {
+            return;
+        }
+        let msgs = self.stable_set.tick();
+        self.send_msgs(msgs);
+
+        // the latest lies may have been lost
+        let generation = self.stable_set.generation();
+        let latest = self
+            .lies
+            .range((generation, 0)..=(generation, u64::MAX))
+            .next_back()
+            .map(|(_, lies)| lies.clone());
+        if let Some(lies) = latest {
+            self.send_lies(lies);
+        }
+
+        if self.fault == Fault::Replay {
+            self.replay();
+            self.replay_captured();
+        }
+        if self.fault == Fault::ForgeSync {
+            let our_id = self.stable_set.id();
+            let members: Vec<_> = self.stable_set.members().iter().copied().collect();
+            for member in members.into_iter().filter(|member| member.id != our_id) {
+                self.forge_sync(member);
+            }
+        }
+    }
+
+    fn handle_msg(&mut self, msg: MsgReceived<StableSetMsg>) {
+        if self.fault == Fault::Silent {
+            return;
+        }
+        if self.received.len() < REPLAY_BUFFER {
+            self.received.push(msg.wire_msg.clone());
+        }
+
+        let sender = msg.sender;
+        let payload = match msg.wire_msg.body {
+            Ok(payload) => payload,
+            Err(_) => return,
+        };
+        match payload {
+            StableSetMsg::Ping => self.send_msg(sender, msg.wire_msg.id, StableSetMsg::Pong),
+            StableSetMsg::Pong => {}
+            StableSetMsg::ReqJoin(node) if self.fault == Fault::ForgeSync => {
+                let msgs = self
+                    .stable_set
+                    .handle_msg(sender, StableSetMsg::ReqJoin(node));
+                self.send_msgs(msgs);
+                self.forge_sync(node);
+            }
+            payload => {
+                let witnessed = match &payload {
+                    StableSetMsg::Witness(witness) => Some(*witness),
+                    _ => None,
+                };
+                let msgs = self.stable_set.handle_msg(sender, payload);
+                self.send_msgs(msgs);
+                if let Some(witness) = witnessed {
+                    self.lie(witness);
+                }
+            }
+        }
+    }
+
+    /// Votes in the witness' round, one way to some members and another way to others.
+    fn lie(&mut self, witness: Witness) {
+        let generation = self.stable_set.generation();
+        let key = (generation, witness.round);
+        if witness.generation != generation || self.lies.contains_key(&key) {
+            return;
+        }
+        if !matches!(self.fault, Fault::Equivocate | Fault::ConflictingWitnesses) {
+            return;
+        }
+        let change = match witness.ballot {
+            Ballot::Change(change) | Ballot::Commit(change) => change,
+            Ballot::Skip => return,
+        };
+        let changes = match self.alternative(&change) {
+            Some(other) => [change, other],
+            None => return,
+        };
+        let sign = |ballot| Witness::new(&self.keypair, generation, witness.round, ballot);
+        let witnesses =
+            changes.map(|change| [sign(Ballot::Change(change)), sign(Ballot::Commit(change))]);
+
+        let our_id = self.stable_set.id();
+        let mut peers: Vec<_> = self
+            .stable_set
+            .members()
+            .iter()
+            .filter(|member| member.id != our_id)
+            .copied()
+            .collect();
+        peers.shuffle(&mut self.rng);
+        let mut lies = Outgoing::new();
+        for (index, peer) in peers.into_iter().enumerate() {
+            match self.fault {
+                Fault::Equivocate => {
+                    let mut witnesses = witnesses.concat();
+                    witnesses.shuffle(&mut self.rng);
+                    for witness in witnesses {
+                        lies.push((peer, StableSetMsg::Witness(witness)));
+                    }
+                }
+                _ => {
+                    for witness in witnesses[index % 2] {
+                        lies.push((peer, StableSetMsg::Witness(witness)));
+                    }
+                }
+            }
+        }
+        let _ = self.lies.insert(key, lies.clone());
+        self.send_lies(lies);
+    }
+
+    /// Another change that would apply to the set: evicting one of the other members.
+    fn alternative(&self, change: &Change) -> Option<Change> {
+        let our_id = self.stable_set.id();
+        self.stable_set
+            .members()
+            .iter()
+            .map(|member| Change::Leave(member.id))
+            .find(|other| other != change && *other != Change::Leave(our_id))
+    }
+
+    /// Sends the node histories of the set made up to evict everyone but us.
+    fn forge_sync(&mut self, node: NetworkNode) {
+        let our_id = self.stable_set.id();
+        let generation = self.stable_set.generation();
+        let membership = self.stable_set.membership();
+        let base = membership.base().clone();
+        let log = membership.log().to_vec();
+        let eviction = self
+            .stable_set
+            .members()
+            .iter()
+            .find(|member| member.id != our_id)
+            .map(|member| self.forge_cert(generation, Change::Leave(member.id)));
+        let eviction = match eviction {
+            Some(eviction) => eviction,
+            None => return,
+        };
+
+        let sync = StableSetMsg::Sync {
+            generation,
+            certs: vec![eviction.clone()],
+        };
+        let mut certs = log;
+        certs.push(eviction);
+        let snapshot = StableSetMsg::SyncSnapshot { base, certs };
+        let our_own = StableSetMsg::SyncSnapshot {
+            base: MembershipSnapshot {
+                generation: generation + FORGED_GENERATIONS,
+                members: BTreeSet::from([self.comm.our_node(), node]),
+            },
+            certs: vec![],
+        };
+        for msg in [sync, snapshot, our_own] {
+            self.send_msg(node, MsgId::new(), msg);
+        }
+    }
+
+    /// A certificate of the change, committed to by us alone.
+    fn forge_cert(&self, generation: u64, change: Change) -> QuorumCert {
+        let commit = Witness::new(&self.keypair, generation, 0, Ballot::Commit(change));
+        QuorumCert {
+            generation,
+            round: 0,
+            change,
+            signatures: BTreeMap::from([(self.stable_set.id(), commit.signature)]),
+        }
+    }
+
+    /// Sends random old msgs again, ours to the same recipient,
+    /// and other members' to random members, on their behalf.
+    fn replay(&mut self) {
+        let our_id = self.stable_set.id();
+        let members: Vec<_> = self.stable_set.members().iter().copied().collect();
+        for _ in 0..REPLAYS_PER_TICK {
+            let replayed = if self.rng.gen_bool(0.5) {
+                self.sent.choose(&mut self.rng).cloned()
+            } else {
+                match (
+                    self.received.choose(&mut self.rng),
+                    members.choose(&mut self.rng),
+                ) {
+                    (Some(msg), Some(member)) if member.id != msg.src && member.id != our_id => {
+                        Some((*member, msg.clone()))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some((node, msg)) = replayed {
+                if let Ok(frame) = msg.to_frame() {
+                    self.comm
+                        .send_out_frame(node, msg.id, frame, SendOptions::default());
+                }
+            }
+        }
+    }
+
+    /// Sends random msgs other members sent over the network again, to their original recipients,
+    /// as if those members had moved to our address.
+    fn replay_captured(&mut self) {
+        let our_id = self.stable_set.id();
+        let captured = self.tap.captured();
+        for _ in 0..REPLAYS_PER_TICK {
+            let (to, frame) = match captured.choose(&mut self.rng) {
+                Some(captured) => captured.clone(),
+                None => return,
+            };
+            let msg = NetworkMsg::<StableSetMsg>::from_frame(frame.clone(), DEFAULT_MAX_MSG_SIZE);
+            let (id, dst) = match msg {
+                Ok(msg) if msg.src != our_id => match msg.dst {
+                    Some(dst) if dst != our_id => (msg.id, dst),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let node = NetworkNode { id: dst, addr: to };
+            self.comm
+                .send_out_frame(node, id, frame, SendOptions::default());
+        }
+    }
+
+    fn send_lies(&mut self, lies: Outgoing) {
+        for (peer, msg) in lies {
+            self.send_msg(peer, MsgId::new(), msg);
+        }
+    }
+
+    /// Sends the msgs out honestly, except for its own votes, which only go out as lies.
+    fn send_msgs(&mut self, msgs: Outgoing) {
+        let lying = matches!(self.fault, Fault::Equivocate | Fault::ConflictingWitnesses);
+        for (node, msg) in msgs {
+            if lying && matches!(msg, StableSetMsg::Witness(_)) {
+                continue;
+            }
+            self.send_msg(node, MsgId::new(), msg);
+        }
+    }
+
+    fn send_msg(&mut self, node: NetworkNode, id: MsgId, payload: StableSetMsg) {
+        let msg = match self.comm.signed_msg(id, node.id, payload) {
+            Ok(msg) => msg,
+            Err(_) => return,
+        };
+        if let Ok(frame) = msg.to_frame() {
+            self.comm
+                .send_out_frame(node, msg.id, frame, SendOptions::default());
+        }
+        if self.sent.len() < REPLAY_BUFFER {
+            self.sent.push((node, msg));
+        }
+    }
+}
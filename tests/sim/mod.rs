@@ -0,0 +1,499 @@
+//! Deterministic simulation of a stable set cluster.
+//!
+//! Nodes run the real node runtime over a `MemoryNetwork`, on tokio's paused clock,
+//! so time only moves forward when every node is idle. The fate of every msg sent,
+//! its delay, loss, duplication or blocking by a partition, is drawn from an RNG seeded
+//! by the test, which makes a run with the same seed unfold the same way.
+
+// each test binary uses a different part of the simulator
+#![allow(dead_code)]
+
+mod byzantine;
+mod safety;
+
+pub use self::byzantine::{Adversary, AdversaryHandle, Fault};
+pub use self::safety::SafetyChecker;
+
+use stableset_net::{
+    comms::{
+        Comm, CommConfig, CommEvent, Frame, Keypair, MemoryNetwork, NetworkFaults, NetworkNode,
+        RetryPolicy,
+    },
+    stableset::{join_stable_set, run_stable_set, MembershipSnapshot, NodeHandle, StableSetMsg},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    env, fmt, fs,
+    future::Future,
+    net::SocketAddr,
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, Duration, Instant},
+};
+
+/// How the simulated network treats the msgs sent over it.
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Probability for a msg to be lost.
+    pub loss: f64,
+    /// Probability for a msg to be delivered twice.
+    pub duplication: f64,
+}
+
+impl NetConfig {
+    /// Every msg is delivered once, after a short random delay.
+    pub fn reliable() -> Self {
+        Self {
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+            loss: 0.0,
+            duplication: 0.0,
+        }
+    }
+
+    /// Msgs get reordered by widely varying delays, some are lost and some duplicated.
+    pub fn lossy() -> Self {
+        Self {
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(400),
+            loss: 0.05,
+            duplication: 0.05,
+        }
+    }
+}
+
+/// The seeds to run a scenario with: the first of the passed in ones, all of them
+/// if the `SIM_SWEEP` env var is set, or the one in the `SIM_SEED` env var, to reproduce a failed run.
+pub fn seeds(sweep: Range<u64>) -> Vec<u64> {
+    if let Ok(seed) = env::var("SIM_SEED") {
+        return vec![seed.parse().expect("SIM_SEED must be a number")];
+    }
+    if env::var_os("SIM_SWEEP").is_some() {
+        return sweep.collect();
+    }
+    sweep.take(1).collect()
+}
+
+/// Runs the simulation to completion on a fresh single threaded runtime with a paused clock.
+pub fn run<F: Future>(simulation: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .expect("Failed to build the simulation runtime")
+        .block_on(simulation)
+}
+
+/// What happened to a msg on the simulated network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Delivered,
+    Duplicated,
+    Lost,
+    Blocked,
+}
+
+/// A msg sent over the simulated network, and what became of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Time since the start of the simulation.
+    pub at: Duration,
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    pub len: usize,
+    pub fate: Fate,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>10.3}s {} -> {} {}B {:?}",
+            self.at.as_secs_f64(),
+            self.from,
+            self.to,
+            self.len,
+            self.fate
+        )
+    }
+}
+
+#[derive(Debug)]
+struct NetState {
+    config: NetConfig,
+    rng: StdRng,
+    start: Instant,
+    /// Groups of nodes which can only reach each other, if the network is partitioned.
+    partition: Option<Vec<BTreeSet<SocketAddr>>>,
+    trace: Vec<TraceEntry>,
+    /// The latest frames sent, along with the address they were sent to, for eavesdroppers.
+    captured: VecDeque<(SocketAddr, Frame)>,
+}
+
+impl NetState {
+    fn can_reach(&self, from: SocketAddr, to: SocketAddr) -> bool {
+        match &self.partition {
+            Some(groups) => groups
+                .iter()
+                .any(|group| group.contains(&from) && group.contains(&to)),
+            None => true,
+        }
+    }
+}
+
+/// Number of the latest frames sent kept around for eavesdroppers.
+const CAPTURE_LIMIT: usize = 1_000;
+
+/// The faults of the simulated network, which the nodes are connected to over a `MemoryNetwork`.
+#[derive(Debug)]
+pub struct NetFaults {
+    state: Mutex<NetState>,
+}
+
+impl NetFaults {
+    pub fn new(seed: u64, config: NetConfig) -> Self {
+        let state = NetState {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            start: Instant::now(),
+            partition: None,
+            trace: vec![],
+            captured: VecDeque::new(),
+        };
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Splits the network, nodes can then only reach the nodes of their own group.
+    /// Nodes in none of the groups are cut off from everyone.
+    pub fn partition(&self, groups: Vec<BTreeSet<SocketAddr>>) {
+        self.lock().partition = Some(groups);
+    }
+
+    pub fn heal(&self) {
+        self.lock().partition = None;
+    }
+
+    pub fn set_config(&self, config: NetConfig) {
+        self.lock().config = config;
+    }
+
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        self.lock().trace.clone()
+    }
+
+    /// The latest frames sent over the network, along with the address each was sent to.
+    pub fn captured(&self) -> Vec<(SocketAddr, Frame)> {
+        self.lock().captured.iter().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, NetState> {
+        self.state.lock().expect("A simulation task panicked")
+    }
+}
+
+impl NetworkFaults for NetFaults {
+    fn deliveries(&self, from: SocketAddr, to: SocketAddr, frame: &Frame) -> Vec<Duration> {
+        let mut state = self.lock();
+        let config = state.config.clone();
+        let (fate, copies) = if !state.can_reach(from, to) {
+            (Fate::Blocked, 0)
+        } else if state.rng.gen_bool(config.loss) {
+            (Fate::Lost, 0)
+        } else if state.rng.gen_bool(config.duplication) {
+            (Fate::Duplicated, 2)
+        } else {
+            (Fate::Delivered, 1)
+        };
+        let at = state.start.elapsed();
+        state.trace.push(TraceEntry {
+            at,
+            from,
+            to,
+            len: frame.len(),
+            fate,
+        });
+        if state.captured.len() == CAPTURE_LIMIT {
+            let _ = state.captured.pop_front();
+        }
+        state.captured.push_back((to, frame.clone()));
+        (0..copies)
+            .map(|_| state.rng.gen_range(config.min_delay..=config.max_delay))
+            .collect()
+    }
+
+    /// Partitions are checked again on delivery.
+    fn can_deliver(&self, from: SocketAddr, to: SocketAddr) -> bool {
+        self.lock().can_reach(from, to)
+    }
+}
+
+/// How often `run_checked` checks the safety invariants, in virtual time.
+const SAFETY_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A node of the simulation.
+#[derive(Debug)]
+pub struct SimNode {
+    pub node: NetworkNode,
+    pub handle: NodeHandle,
+}
+
+/// A cluster of nodes running over a simulated network.
+#[derive(Debug)]
+pub struct Sim {
+    pub seed: u64,
+    pub network: MemoryNetwork,
+    pub net_faults: Arc<NetFaults>,
+    /// The honest nodes, which the safety invariants are checked at.
+    pub nodes: Vec<SimNode>,
+    /// The faulty members.
+    pub adversaries: Vec<AdversaryHandle>,
+    safety: SafetyChecker,
+    rng: StdRng,
+    genesis: BTreeSet<NetworkNode>,
+    /// Number of nodes ever started, to give each its own address.
+    started: u32,
+}
+
+impl Sim {
+    /// Starts a genesis set of the given size.
+    /// Keys and network behaviour are derived from the seed.
+    pub fn new(seed: u64, size: usize, config: NetConfig) -> Self {
+        Self::with_faults(seed, size, &[], config)
+    }
+
+    /// Starts a genesis set of `honest` nodes, plus a faulty member for each of the faults.
+    pub fn with_faults(seed: u64, honest: usize, faults: &[Fault], config: NetConfig) -> Self {
+        let net_faults = Arc::new(NetFaults::new(seed, config));
+        let mut sim = Self {
+            seed,
+            network: MemoryNetwork::with_faults(net_faults.clone()),
+            net_faults,
+            nodes: vec![],
+            adversaries: vec![],
+            safety: SafetyChecker::default(),
+            rng: StdRng::seed_from_u64(seed),
+            genesis: BTreeSet::new(),
+            started: 0,
+        };
+        let mut comms: Vec<_> = (0..honest + faults.len()).map(|_| sim.new_comm()).collect();
+        sim.genesis = comms.iter().map(|(comm, _)| comm.our_node()).collect();
+        let faulty = comms.split_off(honest);
+        for (comm, receiver) in comms {
+            let node = comm.our_node();
+            let handle = run_stable_set(comm, receiver, sim.peers_of(&node));
+            sim.nodes.push(SimNode { node, handle });
+        }
+        for ((comm, receiver), fault) in faulty.into_iter().zip(faults) {
+            let peers = sim.peers_of(&comm.our_node());
+            let tap = sim.net_faults.clone();
+            let adversary = Adversary::new(comm, receiver, peers, *fault, tap, sim.rng.gen());
+            sim.adversaries.push(adversary.start());
+        }
+        sim
+    }
+
+    /// Starts a node joining the set through the genesis nodes, returning its index.
+    pub fn add_node(&mut self) -> usize {
+        let (comm, receiver) = self.new_comm();
+        let node = comm.our_node();
+        let handle = join_stable_set(comm, receiver, self.genesis.clone());
+        self.nodes.push(SimNode { node, handle });
+        self.nodes.len() - 1
+    }
+
+    pub fn addr(&self, index: usize) -> SocketAddr {
+        self.nodes[index].node.addr
+    }
+
+    /// Lets the simulation run for the given virtual time.
+    pub async fn run_for(&self, duration: Duration) {
+        sleep(duration).await
+    }
+
+    /// Lets the simulation run for the given virtual time, checking the safety invariants
+    /// at every node along the way. Panics on the first violation, with the seed to reproduce
+    /// it and the path of the msg trace leading to it.
+    pub async fn run_checked(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            sleep(SAFETY_CHECK_INTERVAL).await;
+            self.check_safety().await;
+        }
+    }
+
+    /// Runs the simulation until all nodes agree on a membership passing the check,
+    /// checking the safety invariants at every node along the way, up to the timeout.
+    pub async fn run_checked_until(
+        &mut self,
+        timeout: Duration,
+        done: impl Fn(&MembershipSnapshot) -> bool,
+    ) -> Option<MembershipSnapshot> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            sleep(SAFETY_CHECK_INTERVAL).await;
+            self.check_safety().await;
+            match self.converged().await {
+                Some(snapshot) if done(&snapshot) => return Some(snapshot),
+                _ => continue,
+            }
+        }
+        None
+    }
+
+    /// Checks the safety invariants against the current membership of every node,
+    /// panicking on violations.
+    pub async fn check_safety(&mut self) {
+        for node in &self.nodes {
+            let membership = match node.handle.membership().await {
+                Ok(membership) => membership,
+                // stopped nodes have nothing left to say
+                Err(_) => continue,
+            };
+            if let Err(violation) = self.safety.observe(node.node.id, &membership) {
+                let path = self.write_trace();
+                panic!(
+                    "Safety violated in the simulation with seed {} (rerun with SIM_SEED={}): \
+                    {violation}\nMsg trace written to {}",
+                    self.seed,
+                    self.seed,
+                    path.display()
+                );
+            }
+        }
+    }
+
+    /// Writes the trace of the msgs sent so far to a file, returning its path.
+    pub fn write_trace(&self) -> PathBuf {
+        let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+            .join(format!("stableset-sim-{}.trace", self.seed));
+        let trace: Vec<_> = self
+            .net_faults
+            .trace()
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        fs::write(&path, trace.join("\n")).expect("Failed to write the msg trace");
+        path
+    }
+
+    /// Splits the nodes into groups, by index.
+    pub fn partition(&self, groups: &[&[usize]]) {
+        let groups = groups
+            .iter()
+            .map(|group| group.iter().map(|index| self.addr(*index)).collect())
+            .collect();
+        self.net_faults.partition(groups)
+    }
+
+    pub fn heal(&self) {
+        self.net_faults.heal()
+    }
+
+    /// Stops the node, as if it crashed.
+    pub async fn kill(&mut self, index: usize) -> Option<MembershipSnapshot> {
+        let node = self.nodes.remove(index);
+        node.handle.shutdown().await.ok()
+    }
+
+    /// The membership as seen by each running node, in order.
+    pub async fn snapshots(&self) -> Vec<MembershipSnapshot> {
+        let mut snapshots = vec![];
+        for node in &self.nodes {
+            if let Ok(snapshot) = node.handle.snapshot().await {
+                snapshots.push(snapshot);
+            }
+        }
+        snapshots
+    }
+
+    /// The membership all running nodes agree on, if they do.
+    pub async fn converged(&self) -> Option<MembershipSnapshot> {
+        let snapshots = self.snapshots().await;
+        if snapshots.len() != self.nodes.len() {
+            return None;
+        }
+        let first = snapshots.first()?.clone();
+        snapshots
+            .iter()
+            .all(|snapshot| *snapshot == first)
+            .then_some(first)
+    }
+
+    /// Runs the simulation until all nodes agree on the passed in members,
+    /// checking once per virtual second, up to the timeout.
+    pub async fn converge_to(
+        &self,
+        members: &BTreeSet<NetworkNode>,
+        timeout: Duration,
+    ) -> Option<MembershipSnapshot> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match self.converged().await {
+                Some(snapshot) if snapshot.members == *members => return Some(snapshot),
+                _ => sleep(Duration::from_secs(1)).await,
+            }
+        }
+        None
+    }
+
+    /// Runs the simulation until all nodes agree on the membership,
+    /// checking once per virtual second, up to the timeout.
+    pub async fn converged_within(&self, timeout: Duration) -> Option<MembershipSnapshot> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match self.converged().await {
+                Some(snapshot) => return Some(snapshot),
+                None => sleep(Duration::from_secs(1)).await,
+            }
+        }
+        None
+    }
+
+    pub fn members(&self) -> BTreeSet<NetworkNode> {
+        self.nodes.iter().map(|node| node.node).collect()
+    }
+
+    /// Stops all nodes.
+    pub async fn shutdown(self) {
+        for node in self.nodes {
+            let _ = node.handle.shutdown().await;
+        }
+        for adversary in self.adversaries {
+            adversary.shutdown().await;
+        }
+    }
+
+    /// The genesis members but the passed in one.
+    fn peers_of(&self, node: &NetworkNode) -> BTreeSet<NetworkNode> {
+        let mut peers = self.genesis.clone();
+        let _ = peers.remove(node);
+        peers
+    }
+
+    fn new_comm(&mut self) -> (Comm, mpsc::Receiver<CommEvent<StableSetMsg>>) {
+        self.started += 1;
+        let [.., high, low] = self.started.to_be_bytes();
+        let addr = SocketAddr::from(([10, 0, high, low], 12000));
+        let keypair = Keypair::from_secret(self.rng.gen());
+        let (transport, incoming) = self.network.bind(addr).expect("Failed to bind");
+        // unjittered retries, so that they don't make runs with the same seed differ
+        let config = CommConfig {
+            retry: RetryPolicy {
+                jitter: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Comm::with_config(keypair, Arc::new(transport), incoming, config)
+    }
+}
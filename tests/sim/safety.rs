@@ -0,0 +1,96 @@
+use stableset_net::{
+    comms::{NetworkNode, NodeId},
+    stableset::{Change, Membership},
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Checks the memberships observed over a simulation against the safety invariants
+/// of the stable set:
+/// - all nodes agree on the members at any given generation,
+/// - all nodes agree on the change decided at any given generation, and it is certified,
+/// - each node's membership only ever moves forward.
+#[derive(Debug, Default)]
+pub struct SafetyChecker {
+    /// Latest generation and members observed at each node.
+    latest: BTreeMap<NodeId, (u64, BTreeSet<NodeId>)>,
+    /// Members at each generation, as first observed, and at which node.
+    generations: BTreeMap<u64, (BTreeSet<NodeId>, NodeId)>,
+    /// Change decided on top of each generation, as first observed, and at which node.
+    decisions: BTreeMap<u64, (Change, NodeId)>,
+}
+
+impl SafetyChecker {
+    /// Checks the membership currently held by a node, describing the first violation found.
+    pub fn observe(&mut self, node: NodeId, membership: &Membership) -> Result<(), String> {
+        let generation = membership.generation();
+        let members = ids(membership.members());
+        if generation == 0 && members.is_empty() {
+            // a joining node which has yet to hear from the set
+            return Ok(());
+        }
+        if let Some((latest, latest_members)) = self.latest.get(&node) {
+            if generation < *latest {
+                return Err(format!(
+                    "{node:?} went back from generation {latest} to {generation}"
+                ));
+            }
+            if generation == *latest && members != *latest_members {
+                return Err(format!(
+                    "{node:?} changed members without a new generation {generation}: \
+                    {latest_members:?} then {members:?}"
+                ));
+            }
+        }
+        let _ = self.latest.insert(node, (generation, members));
+
+        for generation in membership.base_generation()..=generation {
+            let members = match membership.members_at(generation) {
+                Some(members) => members,
+                None => continue,
+            };
+            let members_ids = ids(&members);
+            match self.generations.get(&generation) {
+                Some((seen, seen_at)) if *seen != members_ids => {
+                    return Err(format!(
+                        "diverging members at generation {generation}: \
+                        {seen:?} at {seen_at:?}, {members_ids:?} at {node:?}"
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    let _ = self.generations.insert(generation, (members_ids, node));
+                }
+            }
+
+            let cert = match membership.certs_since(generation).and_then(|c| c.first()) {
+                Some(cert) => cert,
+                None => continue,
+            };
+            if !cert.verify(&members) {
+                return Err(format!(
+                    "{node:?} applied {:?} on top of generation {generation} without a valid certificate",
+                    cert.change
+                ));
+            }
+            match self.decisions.get(&generation) {
+                Some((seen, seen_at)) if *seen != cert.change => {
+                    return Err(format!(
+                        "conflicting decisions at generation {generation}: \
+                        {seen:?} at {seen_at:?}, {:?} at {node:?}",
+                        cert.change
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    let _ = self.decisions.insert(generation, (cert.change, node));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ids<'a>(nodes: impl IntoIterator<Item = &'a NetworkNode>) -> BTreeSet<NodeId> {
+    nodes.into_iter().map(|node| node.id).collect()
+}
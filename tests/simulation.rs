@@ -0,0 +1,101 @@
+mod sim;
+
+use sim::{NetConfig, Sim};
+
+use tokio::time::Duration;
+
+const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[test]
+fn joining_node_is_let_in_by_all_members() {
+    sim::run(async {
+        let mut sim = Sim::new(1, 4, NetConfig::reliable());
+        let _ = sim.add_node();
+
+        let snapshot = sim.converge_to(&sim.members(), CONVERGENCE_TIMEOUT).await;
+        assert_eq!(snapshot.map(|s| s.generation), Some(1));
+        sim.shutdown().await;
+    })
+}
+
+#[test]
+fn membership_converges_despite_delay_loss_and_duplication() {
+    for seed in sim::seeds(0..5) {
+        sim::run(async {
+            let mut sim = Sim::new(seed, 4, NetConfig::lossy());
+            let _ = sim.add_node();
+            let _ = sim.add_node();
+
+            let snapshot = sim.converge_to(&sim.members(), CONVERGENCE_TIMEOUT).await;
+            assert!(snapshot.is_some(), "seed {seed} did not converge");
+            sim.shutdown().await;
+        })
+    }
+}
+
+#[test]
+fn crashed_member_gets_evicted() {
+    sim::run(async {
+        let mut sim = Sim::new(2, 4, NetConfig::lossy());
+        sim.run_for(Duration::from_secs(5)).await;
+        let _ = sim.kill(3).await;
+
+        let snapshot = sim.converge_to(&sim.members(), CONVERGENCE_TIMEOUT).await;
+        assert_eq!(snapshot.map(|s| s.members.len()), Some(3));
+        sim.shutdown().await;
+    })
+}
+
+#[test]
+fn leaving_member_is_let_go_by_all_members() {
+    sim::run(async {
+        let mut sim = Sim::new(4, 4, NetConfig::reliable());
+        sim.run_for(Duration::from_secs(5)).await;
+        let leaving = sim.nodes.remove(3);
+
+        let left = leaving.handle.leave().await.expect("Failed to leave");
+        assert!(!left.members.contains(&leaving.node));
+        let _ = leaving.handle.shutdown().await;
+        // the others learn of the leave well before they would have evicted the node
+        let snapshot = sim
+            .converge_to(&sim.members(), Duration::from_secs(2))
+            .await;
+        assert_eq!(snapshot, Some(left));
+        sim.shutdown().await;
+    })
+}
+
+#[test]
+fn isolated_member_catches_up_after_the_partition_heals() {
+    sim::run(async {
+        let sim = Sim::new(3, 4, NetConfig::reliable());
+        let isolated = sim.nodes[3].node;
+        sim.partition(&[&[0, 1, 2], &[3]]);
+
+        // the majority side evicts the node it can't reach, which learns of it once healed
+        let mut majority = sim.members();
+        let _ = majority.remove(&isolated);
+        sim.run_for(Duration::from_secs(20)).await;
+        sim.heal();
+
+        let snapshots = sim.converged_within(CONVERGENCE_TIMEOUT).await;
+        assert_eq!(snapshots.map(|s| s.members), Some(majority));
+        sim.shutdown().await;
+    })
+}
+
+#[test]
+fn same_seed_gives_the_same_run() {
+    let trace = |seed| {
+        sim::run(async move {
+            let mut sim = Sim::new(seed, 4, NetConfig::lossy());
+            let _ = sim.add_node();
+            sim.run_for(Duration::from_secs(10)).await;
+            let trace = sim.net_faults.trace();
+            sim.shutdown().await;
+            trace
+        })
+    };
+    assert_eq!(trace(7), trace(7));
+    assert_ne!(trace(7), trace(8));
+}
@@ -0,0 +1,529 @@
+//! Property tests of the wire encoding of msgs, and of the listener decoding them.
+
+use stableset_net::{
+    comms::{
+        wire::Handshake, Comm, CommConfig, CommEvent, Error, Frame, Keypair, MemoryNetwork, MsgId,
+        NetworkMsg, NetworkNode, NodeId, SendOptions, Transport, DEFAULT_MAX_MSG_SIZE, HEADER_LEN,
+        MAGIC, PROTOCOL_VERSION,
+    },
+    stableset::{Ballot, Change, MembershipSnapshot, QuorumCert, StableSetMsg, Witness},
+};
+
+use bytes::Bytes;
+use ed25519_dalek::Signature;
+use proptest::{collection, prelude::*};
+use std::{
+    net::{SocketAddr, SocketAddrV6},
+    sync::Arc,
+};
+use tokio::time::{timeout, Duration};
+
+fn node_id() -> impl Strategy<Value = NodeId> {
+    any::<[u8; 32]>().prop_map(|secret| Keypair::from_secret(secret).id())
+}
+
+fn addr() -> impl Strategy<Value = SocketAddr> {
+    // serde leaves out the flow info and scope id of IPv6 addresses
+    any::<SocketAddr>().prop_map(|addr| match addr {
+        SocketAddr::V6(addr) => SocketAddrV6::new(*addr.ip(), addr.port(), 0, 0).into(),
+        addr => addr,
+    })
+}
+
+fn network_node() -> impl Strategy<Value = NetworkNode> {
+    (node_id(), addr()).prop_map(|(id, addr)| NetworkNode { id, addr })
+}
+
+fn signature() -> impl Strategy<Value = Signature> {
+    collection::vec(any::<u8>(), 64)
+        .prop_map(|bytes| Signature::from_slice(&bytes).expect("64 bytes make a signature"))
+}
+
+fn change() -> impl Strategy<Value = Change> {
+    prop_oneof![
+        network_node().prop_map(Change::Join),
+        node_id().prop_map(Change::Leave),
+    ]
+}
+
+fn witness() -> impl Strategy<Value = Witness> {
+    let ballot = prop_oneof![
+        change().prop_map(Ballot::Change),
+        change().prop_map(Ballot::Commit),
+        Just(Ballot::Skip),
+    ];
+    (any::<u64>(), any::<u64>(), ballot, signature()).prop_map(
+        |(generation, round, ballot, signature)| Witness {
+            generation,
+            round,
+            ballot,
+            signature,
+        },
+    )
+}
+
+fn quorum_cert() -> impl Strategy<Value = QuorumCert> {
+    (
+        any::<u64>(),
+        any::<u64>(),
+        change(),
+        collection::btree_map(node_id(), signature(), 0..5),
+    )
+        .prop_map(|(generation, round, change, signatures)| QuorumCert {
+            generation,
+            round,
+            change,
+            signatures,
+        })
+}
+
+fn stableset_msg() -> impl Strategy<Value = StableSetMsg> {
+    prop_oneof![
+        Just(StableSetMsg::Ping),
+        Just(StableSetMsg::Pong),
+        network_node().prop_map(StableSetMsg::ReqJoin),
+        node_id().prop_map(StableSetMsg::ReqLeave),
+        witness().prop_map(StableSetMsg::Witness),
+        any::<u64>().prop_map(|generation| StableSetMsg::SyncReq { generation }),
+        (any::<u64>(), collection::vec(quorum_cert(), 0..4))
+            .prop_map(|(generation, certs)| StableSetMsg::Sync { generation, certs }),
+        (
+            any::<u64>(),
+            collection::btree_set(network_node(), 0..8),
+            collection::vec(quorum_cert(), 0..4)
+        )
+            .prop_map(|(generation, members, certs)| StableSetMsg::SyncSnapshot {
+                base: MembershipSnapshot {
+                    generation,
+                    members
+                },
+                certs
+            }),
+    ]
+}
+
+fn signed_msg(secret: [u8; 32], dst: NodeId, payload: StableSetMsg) -> NetworkMsg<StableSetMsg> {
+    NetworkMsg::signed(&Keypair::from_secret(secret), MsgId::new(), dst, payload)
+        .expect("Failed to sign msg")
+}
+
+proptest! {
+    #[test]
+    fn signed_msgs_round_trip(secret in any::<[u8; 32]>(), dst in node_id(), payload in stableset_msg()) {
+        let msg = signed_msg(secret, dst, payload);
+        let decoded = NetworkMsg::<StableSetMsg>::from_frame(msg.to_frame()?, DEFAULT_MAX_MSG_SIZE)?;
+
+        prop_assert_eq!(decoded.id, msg.id);
+        prop_assert_eq!(decoded.src, msg.src);
+        prop_assert_eq!(decoded.dst, msg.dst);
+        prop_assert_eq!(&decoded.body, &msg.body);
+        prop_assert_eq!(decoded.signature, msg.signature);
+        prop_assert!(decoded.verify().is_ok());
+    }
+
+    #[test]
+    fn tampered_msgs_fail_decoding_or_verification(
+        secret in any::<[u8; 32]>(),
+        dst in node_id(),
+        payload in stableset_msg(),
+        index in any::<prop::sample::Index>(),
+        flip in 1..=u8::MAX,
+    ) {
+        let mut bytes = signed_msg(secret, dst, payload).to_bytes()?.to_vec();
+        let index = index.index(bytes.len());
+        bytes[index] ^= flip;
+
+        if let Ok(tampered) = NetworkMsg::<StableSetMsg>::from_bytes(Bytes::from(bytes)) {
+            prop_assert!(tampered.verify().is_err());
+        }
+    }
+
+    #[test]
+    fn decoding_random_bytes_does_not_panic(bytes in collection::vec(any::<u8>(), 0..2048)) {
+        let _ = NetworkMsg::<StableSetMsg>::from_bytes(Bytes::from(bytes));
+    }
+}
+
+proptest! {
+    // every case spins up a runtime and a pair of nodes
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn listener_keeps_going_after_garbage(
+        garbage in collection::vec(garbage_frame(), 1..8),
+        payload in stableset_msg(),
+    ) {
+        let received = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()?
+            .block_on(send_after_garbage(garbage, payload.clone()));
+        prop_assert_eq!(received, Some(payload));
+    }
+}
+
+/// Random frames, half of them behind a valid msg header, so that their payload gets decoded.
+fn garbage_frame() -> impl Strategy<Value = Frame> {
+    let bytes = |max| collection::vec(any::<u8>(), 0..max).prop_map(Bytes::from);
+    (any::<bool>(), bytes(64), bytes(40), bytes(512)).prop_map(|(valid, header, dst, payload)| {
+        let header = if valid {
+            signed_msg(
+                [1; 32],
+                Keypair::from_secret([2; 32]).id(),
+                StableSetMsg::Ping,
+            )
+            .to_frame()
+            .expect("Failed to encode msg")
+            .header
+        } else {
+            header
+        };
+        Frame {
+            header,
+            dst,
+            payload,
+        }
+    })
+}
+
+/// Sends the garbage then a genuine msg to a node, returning the genuine msg's payload
+/// if the node received it.
+async fn send_after_garbage(garbage: Vec<Frame>, payload: StableSetMsg) -> Option<StableSetMsg> {
+    let network = MemoryNetwork::new();
+    let (transport, incoming) = network
+        .bind(([10, 0, 0, 1], 12000).into())
+        .expect("Failed to bind receiver");
+    let receiver_keypair = Keypair::random();
+    let receiver_id = receiver_keypair.id();
+    let (_comm, mut events) =
+        Comm::with_transport::<StableSetMsg>(receiver_keypair, Arc::new(transport), incoming);
+
+    let (sender, _incoming) = network
+        .bind(([10, 0, 0, 2], 12000).into())
+        .expect("Failed to bind sender");
+    let connection = sender
+        .connect(([10, 0, 0, 1], 12000).into())
+        .await
+        .expect("Failed to connect");
+    for frame in garbage {
+        connection
+            .send(frame)
+            .await
+            .expect("Failed to send garbage");
+    }
+    let msg = NetworkMsg::signed(&Keypair::random(), MsgId::new(), receiver_id, payload)
+        .expect("Failed to sign msg");
+    connection
+        .send(msg.to_frame().expect("Failed to encode msg"))
+        .await
+        .expect("Failed to send msg");
+
+    // garbage may happen to decode into some unsigned msg, which gets handed up too
+    timeout(Duration::from_secs(5), async {
+        while let Some(event) = events.recv().await {
+            if let CommEvent::Msg(received) = event {
+                if received.wire_msg.id == msg.id {
+                    return received.wire_msg.body.ok();
+                }
+            }
+        }
+        None
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[test]
+fn huge_length_prefix_is_refused() {
+    let msg = signed_msg(
+        [1; 32],
+        Keypair::from_secret([2; 32]).id(),
+        StableSetMsg::Sync {
+            generation: 7,
+            certs: vec![],
+        },
+    );
+    let mut bytes = msg.to_bytes().expect("Failed to encode msg").to_vec();
+    // the length of the certs comes right before the signature: its option tag and 64 bytes
+    let len_at = bytes.len() - 65 - 8;
+    bytes[len_at..len_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    assert!(NetworkMsg::<StableSetMsg>::from_bytes(Bytes::from(bytes)).is_err());
+}
+
+#[test]
+fn oversized_msgs_are_refused() {
+    let members = (0..64u8)
+        .map(|i| NetworkNode {
+            id: Keypair::from_secret([i; 32]).id(),
+            addr: ([10, 0, 0, i], 12000).into(),
+        })
+        .collect();
+    let msg = signed_msg(
+        [1; 32],
+        Keypair::from_secret([2; 32]).id(),
+        StableSetMsg::SyncSnapshot {
+            base: MembershipSnapshot {
+                generation: 7,
+                members,
+            },
+            certs: vec![],
+        },
+    );
+    let bytes = msg.to_bytes().expect("Failed to encode msg");
+
+    assert!(NetworkMsg::<StableSetMsg>::from_bytes_with_limit(bytes.clone(), 1024).is_err());
+    assert!(NetworkMsg::<StableSetMsg>::from_bytes_with_limit(bytes, 64 * 1024).is_ok());
+}
+
+#[tokio::test]
+async fn listener_reports_oversized_msgs() {
+    let network = MemoryNetwork::new();
+    let (transport, incoming) = network
+        .bind(([10, 0, 0, 1], 12000).into())
+        .expect("Failed to bind receiver");
+    let receiver_keypair = Keypair::random();
+    let receiver_id = receiver_keypair.id();
+    let config = CommConfig {
+        max_msg_size: 1024,
+        ..Default::default()
+    };
+    let (_comm, mut events) =
+        Comm::with_config::<StableSetMsg>(receiver_keypair, Arc::new(transport), incoming, config);
+
+    let (sender, _incoming) = network
+        .bind(([10, 0, 0, 2], 12000).into())
+        .expect("Failed to bind sender");
+    let connection = sender
+        .connect(([10, 0, 0, 1], 12000).into())
+        .await
+        .expect("Failed to connect");
+    let sender_keypair = Keypair::random();
+    let oversized = NetworkMsg::signed(
+        &sender_keypair,
+        MsgId::new(),
+        receiver_id,
+        StableSetMsg::Sync {
+            generation: 0,
+            certs: vec![
+                QuorumCert {
+                    generation: 0,
+                    round: 0,
+                    change: Change::Leave(receiver_id),
+                    signatures: Default::default(),
+                };
+                64
+            ],
+        },
+    )
+    .expect("Failed to sign msg");
+    let small = NetworkMsg::signed(
+        &sender_keypair,
+        MsgId::new(),
+        receiver_id,
+        StableSetMsg::Ping,
+    )
+    .expect("Failed to sign msg");
+    for msg in [&oversized, &small] {
+        connection
+            .send(msg.to_frame().expect("Failed to encode msg"))
+            .await
+            .expect("Failed to send msg");
+    }
+
+    let event = timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("No event for the oversized msg");
+    match event {
+        Some(CommEvent::Error {
+            node_id,
+            error: Error::MessageTooLarge { size, max_size },
+        }) => {
+            assert_eq!(node_id.id, sender_keypair.id());
+            assert!(size > 1024);
+            assert_eq!(max_size, 1024);
+        }
+        other => panic!("Unexpected event {other:?}"),
+    }
+    // the connection is still usable
+    let event = timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("No event for the small msg");
+    match event {
+        Some(CommEvent::Msg(received)) => assert_eq!(received.wire_msg.id, small.id),
+        other => panic!("Unexpected event {other:?}"),
+    }
+}
+
+#[test]
+fn headers_keep_their_layout() {
+    let dst = Keypair::from_secret([2; 32]).id();
+    let msg = signed_msg([1; 32], dst, StableSetMsg::Ping);
+    let frame = msg.to_frame().expect("Failed to encode msg");
+
+    let header = &frame.header[..];
+    assert_eq!(header.len(), HEADER_LEN);
+    assert_eq!(header[..4], MAGIC);
+    assert_eq!(header[4..6], PROTOCOL_VERSION.to_be_bytes());
+    // a msg frame
+    assert_eq!(header[6], 0);
+    // bincode encodes the id little-endian, the header big-endian
+    let mut msg_id = bincode::serialize(&msg.id).expect("Failed to encode msg id");
+    msg_id.reverse();
+    assert_eq!(header[7..15], msg_id[..]);
+    assert_eq!(header[15..47], msg.src.as_bytes()[..]);
+    assert_eq!(frame.dst[..], dst.as_bytes()[..]);
+}
+
+#[test]
+fn the_kind_of_frame_is_signed_along_with_the_msg() {
+    let dst = Keypair::from_secret([2; 32]).id();
+    let keypair = Keypair::from_secret([1; 32]);
+    for msg in [
+        NetworkMsg::signed(&keypair, MsgId::new(), dst, StableSetMsg::Ping),
+        NetworkMsg::signed_reliable(&keypair, MsgId::new(), dst, StableSetMsg::Ping),
+    ] {
+        let msg = msg.expect("Failed to sign msg");
+        let mut frame = msg.to_frame().expect("Failed to encode msg");
+        let decoded = NetworkMsg::<StableSetMsg>::from_frame(frame.clone(), DEFAULT_MAX_MSG_SIZE)
+            .expect("Failed to decode msg");
+        assert_eq!(decoded.reliable, msg.reliable);
+        assert!(decoded.verify().is_ok());
+
+        // a plain msg passed off as a reliable one, or the other way round
+        let mut header = frame.header.to_vec();
+        header[6] = if msg.reliable { 0 } else { 2 };
+        frame.header = Bytes::from(header);
+        let decoded = NetworkMsg::<StableSetMsg>::from_frame(frame, DEFAULT_MAX_MSG_SIZE)
+            .expect("Failed to decode msg");
+        assert!(matches!(decoded.verify(), Err(Error::InvalidSignature(_))));
+    }
+}
+
+#[test]
+fn msgs_of_unsupported_versions_are_refused() {
+    let msg = signed_msg(
+        [1; 32],
+        Keypair::from_secret([2; 32]).id(),
+        StableSetMsg::Ping,
+    );
+    let mut frame = msg.to_frame().expect("Failed to encode msg");
+    let mut header = frame.header.to_vec();
+    assert_eq!(header[..MAGIC.len()], MAGIC);
+    let version = PROTOCOL_VERSION + 1;
+    header[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&version.to_be_bytes());
+    frame.header = Bytes::from(header);
+
+    match NetworkMsg::<StableSetMsg>::from_frame(frame, DEFAULT_MAX_MSG_SIZE) {
+        Err(Error::UnsupportedVersion(unsupported)) => assert_eq!(unsupported, version),
+        other => panic!("Unexpected decoding {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn incompatible_peers_are_refused() {
+    let network = MemoryNetwork::new();
+    let (transport, incoming) = network
+        .bind(([10, 0, 0, 1], 12000).into())
+        .expect("Failed to bind sender");
+    let (comm, mut events) =
+        Comm::with_transport::<StableSetMsg>(Keypair::random(), Arc::new(transport), incoming);
+
+    // a node of some future version, refusing any handshake
+    let (_peer_transport, mut peer_incoming) = network
+        .bind(([10, 0, 0, 2], 12000).into())
+        .expect("Failed to bind peer");
+    let _handle = tokio::spawn(async move {
+        while let Some(mut conn) = peer_incoming.recv().await {
+            while let Some(Ok(msg)) = conn.msgs.recv().await {
+                if let Some(stream) = msg.send_stream {
+                    let _ = stream
+                        .send(refusal(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2))
+                        .await;
+                }
+            }
+        }
+    });
+
+    let peer = NetworkNode {
+        id: Keypair::random().id(),
+        addr: ([10, 0, 0, 2], 12000).into(),
+    };
+    let msg = comm
+        .signed_msg(MsgId::new(), peer.id, StableSetMsg::Ping)
+        .expect("Failed to sign msg");
+    comm.send_out_frame(
+        peer,
+        msg.id,
+        msg.to_frame().expect("Failed to encode msg"),
+        SendOptions::default(),
+    );
+
+    let event = timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("The refusal was not reported");
+    match event {
+        Some(CommEvent::Error {
+            node_id,
+            error:
+                Error::IncompatiblePeer {
+                    min_version,
+                    max_version,
+                },
+        }) => {
+            assert_eq!(node_id, peer);
+            assert_eq!(min_version, PROTOCOL_VERSION + 1);
+            assert_eq!(max_version, PROTOCOL_VERSION + 2);
+        }
+        other => panic!("Unexpected event {other:?}"),
+    }
+}
+
+/// A handshake frame refusing us, from a node speaking only the given versions.
+fn refusal(min_version: u16, max_version: u16) -> Frame {
+    Handshake::Refuse {
+        min_version,
+        max_version,
+    }
+    .to_frame(Keypair::random().id(), None)
+    .expect("Failed to encode handshake")
+}
+
+#[tokio::test]
+async fn misdirected_msgs_are_refused_before_decoding() {
+    let network = MemoryNetwork::new();
+    let (transport, incoming) = network
+        .bind(([10, 0, 0, 1], 12000).into())
+        .expect("Failed to bind receiver");
+    let (_comm, mut events) =
+        Comm::with_transport::<StableSetMsg>(Keypair::random(), Arc::new(transport), incoming);
+
+    let (sender, _incoming) = network
+        .bind(([10, 0, 0, 2], 12000).into())
+        .expect("Failed to bind sender");
+    let connection = sender
+        .connect(([10, 0, 0, 1], 12000).into())
+        .await
+        .expect("Failed to connect");
+    // a msg meant for another node, with a payload that wouldn't even decode
+    let msg = signed_msg([1; 32], Keypair::random().id(), StableSetMsg::Ping);
+    let frame = Frame {
+        payload: Bytes::from_static(b"garbage"),
+        ..msg.to_frame().expect("Failed to encode msg")
+    };
+    connection.send(frame).await.expect("Failed to send msg");
+
+    let event = timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("The misdirected msg was not reported");
+    match event {
+        Some(CommEvent::Error {
+            node_id,
+            error: Error::Misdirected(msg_id),
+        }) => {
+            assert_eq!(node_id.id, msg.src);
+            assert_eq!(msg_id, msg.id);
+        }
+        other => panic!("Unexpected event {other:?}"),
+    }
+}